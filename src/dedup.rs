@@ -0,0 +1,356 @@
+//! Detection of notes that share the same content under different titles.
+//!
+//! Notes are compared by a fingerprint of their normalized plain text. When several
+//! notes share a fingerprint, the most recently modified one is kept and the others
+//! are reported as skipped duplicates. Notes without any text, such as photos,
+//! scans and empty notes, cannot be compared this way and are always kept.
+
+use crate::{fnv1a, frontmatter, plain_text, relative_link, ExportConfig, Note};
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// A note that was skipped because another note has the same content.
+#[derive(Debug, Clone)]
+pub struct SkippedDuplicate {
+    /// The note that was not exported
+    pub note: Note,
+    /// The note that was exported in its place
+    pub kept: Note,
+}
+
+/// Returns a stable fingerprint of a note's normalized plain text.
+///
+/// The HTML is reduced to its text and all runs of whitespace are collapsed, so
+/// notes that only differ in markup or spacing share a fingerprint. All notes
+/// without text, such as image-only notes, share one as well.
+pub fn content_fingerprint(note: &Note) -> u64 {
+    fnv1a(plain_text(&note.content).as_bytes())
+}
+
+/// Splits notes into the ones to export and the skipped duplicates.
+///
+/// Kept notes stay in their original order. Among notes with the same fingerprint
+/// the most recently modified one is kept; if the modification dates cannot be
+/// compared, the first one wins. Notes without text are always kept, as their
+/// fingerprint says nothing about their content.
+pub fn dedup_notes(notes: &[Note]) -> (Vec<Note>, Vec<SkippedDuplicate>) {
+    let mut kept_by_fingerprint: HashMap<u64, usize> = HashMap::new();
+    let mut dropped = vec![false; notes.len()];
+    let mut pairs: Vec<(usize, u64)> = Vec::new();
+
+    for (index, note) in notes.iter().enumerate() {
+        if plain_text(&note.content).is_empty() {
+            continue;
+        }
+        let fingerprint = content_fingerprint(note);
+        match kept_by_fingerprint.get(&fingerprint).copied() {
+            Some(kept) if is_newer(note, &notes[kept]) => {
+                dropped[kept] = true;
+                pairs.push((kept, fingerprint));
+                kept_by_fingerprint.insert(fingerprint, index);
+            }
+            Some(_) => {
+                dropped[index] = true;
+                pairs.push((index, fingerprint));
+            }
+            None => {
+                kept_by_fingerprint.insert(fingerprint, index);
+            }
+        }
+    }
+
+    let kept = notes
+        .iter()
+        .zip(&dropped)
+        .filter(|(_, dropped)| !**dropped)
+        .map(|(note, _)| note.clone())
+        .collect();
+    let skipped = pairs
+        .into_iter()
+        .map(|(index, fingerprint)| SkippedDuplicate {
+            note: notes[index].clone(),
+            kept: notes[kept_by_fingerprint[&fingerprint]].clone(),
+        })
+        .collect();
+
+    (kept, skipped)
+}
+
+/// Writes a small Markdown file at `pointer_path` in place of a skipped duplicate
+/// that links to the kept note's file at `kept_path`.
+///
+/// Both paths come from planning the kept notes and then the skipped ones together,
+/// so a duplicate with the same title as the kept note gets a path of its own.
+///
+/// # Errors
+/// * If the pointer file cannot be written
+pub fn write_pointer_file(
+    skipped: &SkippedDuplicate,
+    pointer_path: &Path,
+    kept_path: &Path,
+    config: &ExportConfig,
+) -> Result<()> {
+    let pointer_dir = pointer_path.parent().unwrap_or(Path::new(""));
+
+    fs::create_dir_all(pointer_dir)
+        .with_context(|| format!("Failed to create directory: {:?}", pointer_dir))?;
+
+    let mut content = frontmatter(&skipped.note, config);
+    content.push_str(&format!(
+        "Duplicate of [{}]({})\n",
+        skipped.kept.title,
+        relative_link(pointer_dir, kept_path)
+    ));
+
+    fs::write(pointer_path, content.as_bytes())
+        .with_context(|| format!("Failed to write pointer file: {:?}", pointer_path))?;
+
+    Ok(())
+}
+
+/// Returns whether `candidate` was modified after `current`.
+fn is_newer(candidate: &Note, current: &Note) -> bool {
+    match (
+        date_sort_key(&candidate.modified),
+        date_sort_key(&current.modified),
+    ) {
+        (Some(candidate), Some(current)) => candidate > current,
+        _ => false,
+    }
+}
+
+/// Parses a modification date into a sortable `(year, month, day, seconds)` key.
+///
+/// Supports ISO dates (`2024-01-31`, `2024-01-31 14:05:00`, `2024-01-31T14:05:00`)
+/// and AppleScript's textual dates (`Wednesday, January 31, 2024 at 2:05:00 PM`,
+/// `Wednesday 31 January 2024 at 14:05:00`).
+fn date_sort_key(date: &str) -> Option<(u32, u32, u32, u32)> {
+    let date = date.trim();
+
+    if let Some((day_part, time_part)) = date
+        .split_once(['T', ' '])
+        .or(Some((date, "")))
+        .filter(|(day_part, _)| day_part.len() == 10 && day_part.as_bytes()[4] == b'-')
+    {
+        let mut fields = day_part.split('-').map(|f| f.parse::<u32>().ok());
+        let year = fields.next()??;
+        let month = fields.next()??;
+        let day = fields.next()??;
+        let seconds = if time_part.is_empty() {
+            0
+        } else {
+            parse_time(time_part)?
+        };
+        return Some((year, month, day, seconds));
+    }
+
+    const MONTHS: [&str; 12] = [
+        "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+    ];
+    let mut year = None;
+    let mut month = None;
+    let mut day = None;
+    let mut seconds = None;
+    let mut pm = None;
+
+    for token in date.split(|c: char| c.is_whitespace() || c == ',') {
+        let lower = token.to_lowercase();
+        if token.contains(':') {
+            seconds = parse_time(token);
+        } else if lower == "am" || lower == "pm" {
+            pm = Some(lower == "pm");
+        } else if let Ok(number) = token.parse::<u32>() {
+            if token.len() == 4 {
+                year = Some(number);
+            } else {
+                day = Some(number);
+            }
+        } else if let Some(index) = MONTHS
+            .iter()
+            .position(|m| lower.len() >= 3 && lower.starts_with(m))
+        {
+            month = Some(index as u32 + 1);
+        }
+    }
+
+    let mut seconds = seconds.unwrap_or(0);
+    match pm {
+        Some(true) if seconds < 12 * 3600 => seconds += 12 * 3600,
+        Some(false) if seconds >= 12 * 3600 => seconds -= 12 * 3600,
+        _ => {}
+    }
+
+    Some((year?, month?, day?, seconds))
+}
+
+/// Parses `HH:MM[:SS]` into seconds since midnight.
+fn parse_time(time: &str) -> Option<u32> {
+    let mut fields = time.split(':').map(|f| f.trim().parse::<u32>().ok());
+    let hours = fields.next()??;
+    let minutes = fields.next()??;
+    let seconds = fields.next().unwrap_or(Some(0))?;
+    Some(hours * 3600 + minutes * 60 + seconds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::write_notes;
+    use tempfile::tempdir;
+
+    fn note(title: &str, content: &str, modified: &str) -> Note {
+        Note {
+            title: String::from(title),
+            content: String::from(content),
+            folder: String::from("Test Folder"),
+            account: String::from("Test Account"),
+            id: format!("id-{}", title),
+            created: String::from("2024-01-01"),
+            modified: String::from(modified),
+            flagged: false,
+            has_reminder: false,
+        }
+    }
+
+    #[test]
+    fn test_content_fingerprint_ignores_markup_and_spacing() {
+        let a = note("A", "<p>Buy milk</p><p>and eggs</p>", "2024-01-01");
+        let b = note("B", "<div>Buy   milk\nand eggs</div>", "2024-01-01");
+        let c = note("C", "<p>Buy bread</p>", "2024-01-01");
+        assert_eq!(content_fingerprint(&a), content_fingerprint(&b));
+        assert_ne!(content_fingerprint(&a), content_fingerprint(&c));
+    }
+
+    #[test]
+    fn test_dedup_keeps_notes_without_text() {
+        let photo = |title: &str, data: &str| {
+            note(
+                title,
+                &format!(r#"<div><img src="data:image/png;base64,{}"/></div>"#, data),
+                "2024-01-01",
+            )
+        };
+        let notes = vec![
+            photo("Cat", "Y2F0"),
+            photo("Beach", "YmVhY2g="),
+            note("Empty", "<div><br></div>", "2024-01-01"),
+        ];
+        assert_eq!(
+            content_fingerprint(&notes[0]),
+            content_fingerprint(&notes[1])
+        );
+
+        let (kept, skipped) = dedup_notes(&notes);
+        let titles: Vec<&str> = kept.iter().map(|note| note.title.as_str()).collect();
+        assert_eq!(titles, vec!["Cat", "Beach", "Empty"]);
+        assert!(skipped.is_empty());
+    }
+
+    #[test]
+    fn test_dedup_writes_only_most_recent_note() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let config = ExportConfig {
+            output_dir: temp_dir.path().to_path_buf(),
+            dedup_by_content: true,
+            ..ExportConfig::default()
+        };
+        let notes = vec![
+            note(
+                "Old Copy",
+                "<p>Same content</p>",
+                "Monday, January 1, 2024 at 9:00:00 AM",
+            ),
+            note(
+                "New Copy",
+                "<p>Same content</p>",
+                "Monday, January 1, 2024 at 1:00:00 PM",
+            ),
+        ];
+
+        let report = write_notes(&notes, &config)?;
+
+        let folder = temp_dir.path().join("Test Folder");
+        assert_eq!(report.written, vec![folder.join("New-Copy.md")]);
+        assert!(folder.join("New-Copy.md").exists());
+        assert!(!folder.join("Old-Copy.md").exists());
+        assert_eq!(report.skipped_duplicates.len(), 1);
+        assert_eq!(report.skipped_duplicates[0].note.title, "Old Copy");
+        assert_eq!(report.skipped_duplicates[0].kept.title, "New Copy");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_dedup_pointer_file_links_to_kept_note() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let config = ExportConfig {
+            output_dir: temp_dir.path().to_path_buf(),
+            dedup_by_content: true,
+            dedup_pointer_files: true,
+            ..ExportConfig::default()
+        };
+        let notes = vec![
+            note("Kept", "<p>Same content</p>", "2024-02-01"),
+            note("Copy", "<p>Same content</p>", "2024-01-01"),
+        ];
+
+        write_notes(&notes, &config)?;
+
+        let pointer = fs::read_to_string(temp_dir.path().join("Test Folder").join("Copy.md"))?;
+        assert!(pointer.contains("Duplicate of [Kept](Kept.md)"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_dedup_pointer_file_for_same_title() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let config = ExportConfig {
+            output_dir: temp_dir.path().to_path_buf(),
+            dedup_by_content: true,
+            dedup_pointer_files: true,
+            ..ExportConfig::default()
+        };
+        let kept = note("Copy", "<p>Same content</p>", "2024-02-01");
+        let copy = Note {
+            id: String::from("id-other"),
+            ..note("Copy", "<p>Same content</p>", "2024-01-01")
+        };
+
+        let report = write_notes(&[copy, kept], &config)?;
+
+        let folder = temp_dir.path().join("Test Folder");
+        let pointer_path = folder.join(format!("Copy-{}.md", crate::short_id("id-other")));
+        assert_eq!(report.written, vec![folder.join("Copy.md")]);
+        assert_eq!(report.pointers, vec![pointer_path.clone()]);
+
+        let kept = fs::read_to_string(folder.join("Copy.md"))?;
+        assert!(kept.contains("id: \"id-Copy\""));
+        assert!(kept.contains("Same content"));
+        let pointer = fs::read_to_string(pointer_path)?;
+        assert!(pointer.contains("id: \"id-other\""));
+        assert!(pointer.contains("Duplicate of [Copy](Copy.md)"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_date_sort_key() {
+        assert_eq!(date_sort_key("2024-01-31"), Some((2024, 1, 31, 0)));
+        assert_eq!(
+            date_sort_key("2024-01-31T14:05:00"),
+            Some((2024, 1, 31, 50700))
+        );
+        assert_eq!(
+            date_sort_key("Wednesday, January 31, 2024 at 2:05:00 PM"),
+            Some((2024, 1, 31, 50700))
+        );
+        assert_eq!(
+            date_sort_key("Wednesday 31 January 2024 at 14:05:00"),
+            Some((2024, 1, 31, 50700))
+        );
+        assert_eq!(date_sort_key("yesterday"), None);
+    }
+}
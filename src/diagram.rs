@@ -0,0 +1,118 @@
+//! Mermaid diagram of the account → folder hierarchy with note counts.
+//!
+//! The diagram is written as plain Mermaid source, or wrapped in a ```` ```mermaid ````
+//! block when the output path has a `.md` extension so that it renders in Markdown
+//! viewers. Accounts and folders get their own colors.
+
+use crate::outline::{build_outline, OutlineAccount};
+use crate::{ExportConfig, Note};
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::Path;
+
+/// Renders the Mermaid graph of the given outline.
+pub fn render_mermaid(accounts: &[OutlineAccount]) -> String {
+    let mut mermaid = String::new();
+    mermaid.push_str("graph LR\n");
+    mermaid.push_str("    classDef account fill:#ffd478,stroke:#b8860b,color:#000\n");
+    mermaid.push_str("    classDef folder fill:#cfe2ff,stroke:#3d6bb3,color:#000\n");
+
+    for (a, account) in accounts.iter().enumerate() {
+        let account_node = format!("a{}", a);
+        mermaid.push_str(&format!(
+            "    {}[\"{}\"]:::account\n",
+            account_node,
+            escape_label(&account.name)
+        ));
+        for (f, folder) in account.folders.iter().enumerate() {
+            let count = folder.notes.len();
+            mermaid.push_str(&format!(
+                "    {} --> {}f{}[\"{} ({} {})\"]:::folder\n",
+                account_node,
+                account_node,
+                f,
+                escape_label(&folder.name),
+                count,
+                if count == 1 { "note" } else { "notes" }
+            ));
+        }
+    }
+
+    mermaid
+}
+
+/// Writes the structure diagram of the given notes to `path`.
+///
+/// # Errors
+/// * If the outline of the notes cannot be built
+/// * If the diagram file cannot be written
+pub fn write_structure_diagram(notes: &[Note], config: &ExportConfig, path: &Path) -> Result<()> {
+    let mermaid = render_mermaid(&build_outline(notes, config, &config.output_dir)?);
+
+    let content = if path.extension().is_some_and(|ext| ext == "md") {
+        format!("```mermaid\n{}```\n", mermaid)
+    } else {
+        mermaid
+    };
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory: {:?}", parent))?;
+    }
+    fs::write(path, content.as_bytes())
+        .with_context(|| format!("Failed to write structure diagram: {:?}", path))?;
+
+    Ok(())
+}
+
+/// Escapes text for a quoted Mermaid node label.
+fn escape_label(text: &str) -> String {
+    text.replace('"', "#quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn note(title: &str, folder: &str) -> Note {
+        Note {
+            title: String::from(title),
+            content: String::from("<p>Content</p>"),
+            folder: String::from(folder),
+            account: String::from("iCloud"),
+            id: format!("id-{}", title),
+            created: String::from("2024-01-01"),
+            modified: String::from("2024-01-01"),
+            flagged: false,
+            has_reminder: false,
+        }
+    }
+
+    #[test]
+    fn test_structure_diagram_counts_notes_per_folder() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let diagram_path = temp_dir.path().join("structure.md");
+        let config = ExportConfig {
+            output_dir: temp_dir.path().to_path_buf(),
+            emit_structure_diagram: Some(diagram_path.clone()),
+            ..ExportConfig::default()
+        };
+        let notes = vec![
+            note("Groceries", "Personal"),
+            note("Roadmap", "Work"),
+            note("Trips", "Personal"),
+        ];
+
+        crate::write_notes(&notes, &config)?;
+
+        let diagram = fs::read_to_string(&diagram_path)?;
+        assert!(diagram.starts_with("```mermaid\ngraph LR\n"));
+        assert!(diagram.contains("    a0[\"iCloud\"]:::account\n"));
+        assert!(diagram.contains("    a0 --> a0f0[\"Personal (2 notes)\"]:::folder\n"));
+        assert!(diagram.contains("    a0 --> a0f1[\"Work (1 note)\"]:::folder\n"));
+        assert!(diagram.ends_with("```\n"));
+
+        Ok(())
+    }
+}
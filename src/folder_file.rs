@@ -0,0 +1,362 @@
+//! Export of each folder as a single Markdown file.
+//!
+//! All notes of a folder are concatenated, sorted by title, into `<Folder>.md` in the
+//! output directory. Each note becomes a `##` section, and the images of all notes of
+//! the folder share the `attachments/<Folder>/` directory.
+//!
+//! Folders of different accounts are kept apart unless `merge_accounts` is set. When
+//! two folders would get the same file name, the later one has the short id of its
+//! account and folder name appended.
+
+use crate::markdown::{classify, Block};
+use crate::{
+    render_markdown, sanitize_filename, short_id, AttachmentTarget, ExportConfig, ExportReport,
+    ExportedAttachment, Note,
+};
+use anyhow::{Context, Result};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A folder exported as one combined file, with the notes it contains.
+pub(crate) struct FolderFile<'a> {
+    /// The account of the folder, empty with `merge_accounts`
+    pub(crate) account: &'a str,
+    /// The folder name
+    pub(crate) folder: &'a str,
+    /// Filename of the combined file, without extension
+    pub(crate) stem: String,
+    /// The notes of the folder, in the order they were given
+    pub(crate) notes: Vec<&'a Note>,
+}
+
+impl FolderFile<'_> {
+    /// Returns the path of the combined Markdown file.
+    pub(crate) fn path(&self, config: &ExportConfig) -> PathBuf {
+        config.output_dir.join(format!("{}.md", self.stem))
+    }
+
+    /// Whether `note` belongs to this folder.
+    pub(crate) fn contains(&self, note: &Note, config: &ExportConfig) -> bool {
+        folder_key(note, config) == (self.account, self.folder)
+    }
+}
+
+/// Returns the account and folder a note is grouped by.
+fn folder_key<'a>(note: &'a Note, config: &ExportConfig) -> (&'a str, &'a str) {
+    let account = if config.merge_accounts {
+        ""
+    } else {
+        &note.account
+    };
+    (account, &note.folder)
+}
+
+/// Groups notes into their combined folder files.
+///
+/// Folders are kept in the order in which they first appear in `notes`. Filenames
+/// are compared case-insensitively, like on the default macOS filesystem.
+pub(crate) fn plan_folder_files<'a>(
+    notes: &'a [Note],
+    config: &ExportConfig,
+) -> Vec<FolderFile<'a>> {
+    let mut folders: Vec<FolderFile> = Vec::new();
+    for note in notes {
+        match folders.iter_mut().find(|f| f.contains(note, config)) {
+            Some(folder) => folder.notes.push(note),
+            None => {
+                let (account, folder) = folder_key(note, config);
+                folders.push(FolderFile {
+                    account,
+                    folder,
+                    stem: String::new(),
+                    notes: vec![note],
+                });
+            }
+        }
+    }
+
+    let mut used = HashSet::new();
+    for folder in &mut folders {
+        let mut stem = sanitize_filename(folder.folder);
+        if !used.insert(stem.to_lowercase()) {
+            let key = format!("{}/{}", folder.account, folder.folder);
+            stem = format!("{}-{}", stem, short_id(&key));
+            used.insert(stem.to_lowercase());
+        }
+        folder.stem = stem;
+    }
+
+    folders
+}
+
+/// Writes one combined Markdown file per folder.
+///
+/// Folders are written in the order in which they first appear in `notes`. The
+/// written files and extracted images are added to `report`, and `on_note_done` is
+/// called after each note has been rendered.
+///
+/// # Errors
+/// * If any note cannot be converted
+/// * If a folder file cannot be written
+pub fn write_folder_files(
+    notes: &[Note],
+    config: &ExportConfig,
+    report: &mut ExportReport,
+    on_note_done: &mut dyn FnMut(&Note),
+) -> Result<()> {
+    for mut folder in plan_folder_files(notes, config) {
+        folder.notes.sort_by(|a, b| a.title.cmp(&b.title));
+
+        let path = folder.path(config);
+        let content = render_folder(&folder, config, &path, report, on_note_done)?;
+        fs::write(&path, content.as_bytes())
+            .with_context(|| format!("Failed to write folder file: {:?}", path))?;
+        report.written.push(path);
+    }
+
+    Ok(())
+}
+
+/// Renders the combined Markdown of one folder, in the order of its notes.
+fn render_folder(
+    folder_file: &FolderFile,
+    config: &ExportConfig,
+    path: &Path,
+    report: &mut ExportReport,
+    on_note_done: &mut dyn FnMut(&Note),
+) -> Result<String> {
+    let folder = folder_file.folder;
+    let notes = &folder_file.notes;
+    let stem = &folder_file.stem;
+    let mut target = AttachmentTarget::new(
+        config.output_dir.join("attachments").join(stem),
+        format!("attachments/{}/", stem),
+        notes[0],
+        config,
+    );
+
+    let mut content = String::new();
+    content.push_str("---\n");
+    content.push_str(&format!("folder: \"{}\"\n", folder));
+    content.push_str(&format!("notes: {}\n", notes.len()));
+    content.push_str("---\n\n");
+    content.push_str(&format!("# {}\n", folder));
+
+    for note in notes {
+        target.start_note(note);
+        let markdown = render_markdown(note, config, &mut target, true)?;
+        report
+            .attachments
+            .extend(target.flush()?.into_iter().map(|image| ExportedAttachment {
+                path: image,
+                note_title: note.title.clone(),
+                note_path: path.to_path_buf(),
+            }));
+        content.push_str(&format!("\n## {}\n\n", note.title));
+        content.push_str(demote_headings(&markdown).trim());
+        content.push('\n');
+        on_note_done(note);
+    }
+
+    Ok(content)
+}
+
+/// Demotes every Markdown heading by two levels so it nests below the note section.
+///
+/// Setext headings, which html2md writes for `<h1>` and `<h2>`, become ATX headings.
+/// Lines inside code fences are left alone.
+fn demote_headings(markdown: &str) -> String {
+    classify(markdown)
+        .iter()
+        .map(|block| match block {
+            Block::Blank => String::new(),
+            Block::Content(line) => line.to_string(),
+            Block::Heading { level, lines, .. } => {
+                let hashes = "#".repeat((level + 2).min(6));
+                let first = lines[0].trim_start();
+                if lines.len() == 2 {
+                    format!("{} {}", hashes, first.trim_end())
+                } else if first.starts_with('#') {
+                    format!("{}{}", hashes, &first[*level..])
+                } else {
+                    // An underline without text above it has nothing to demote
+                    lines[0].to_string()
+                }
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::write_notes;
+    use tempfile::tempdir;
+
+    fn note(title: &str, folder: &str, content: &str) -> Note {
+        Note {
+            title: String::from(title),
+            content: String::from(content),
+            folder: String::from(folder),
+            account: String::from("iCloud"),
+            id: format!("id-{}", title),
+            created: String::from("2024-01-01"),
+            modified: String::from("2024-01-01"),
+            flagged: false,
+            has_reminder: false,
+        }
+    }
+
+    #[test]
+    fn test_file_per_folder_writes_one_file_per_folder() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let config = ExportConfig {
+            output_dir: temp_dir.path().to_path_buf(),
+            file_per_folder: true,
+            ..ExportConfig::default()
+        };
+        let notes = vec![
+            note("Trips", "Personal", "<p>Visit Lisbon</p>"),
+            note("Roadmap", "Work", "<p>Ship v2</p>"),
+            note("Groceries", "Personal", "<h1>List</h1><p>Buy milk</p>"),
+        ];
+
+        let report = write_notes(&notes, &config)?;
+        let personal_path = temp_dir.path().join("Personal.md");
+        let work_path = temp_dir.path().join("Work.md");
+        assert_eq!(
+            report.written,
+            vec![personal_path.clone(), work_path.clone()]
+        );
+
+        let personal = fs::read_to_string(personal_path)?;
+        let groceries = personal.find("## Groceries").unwrap();
+        let trips = personal.find("## Trips").unwrap();
+        assert!(groceries < trips);
+        assert!(personal.contains("### List"));
+        assert!(personal.contains("Buy milk"));
+        assert!(personal.contains("Visit Lisbon"));
+        assert!(!personal.contains("Ship v2"));
+
+        let work = fs::read_to_string(work_path)?;
+        assert!(work.starts_with("---\nfolder: \"Work\"\nnotes: 1\n---\n\n# Work\n"));
+        assert!(work.contains("## Roadmap"));
+        assert!(work.contains("Ship v2"));
+        assert!(!work.contains("Buy milk"));
+
+        assert!(!temp_dir.path().join("Personal").exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_file_per_folder_shares_attachments_dir() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let config = ExportConfig {
+            output_dir: temp_dir.path().to_path_buf(),
+            file_per_folder: true,
+            ..ExportConfig::default()
+        };
+        let image = r#"<img src="data:image/png;base64,iVBORw0KGgoAAAANSUhEUgAAAAEAAAABCAQAAAC1HAwCAAAAC0lEQVR42mNk+A8AAQUBAScY42YAAAAASUVORK5CYII="/>"#;
+        let notes = vec![note("A", "Work", image), note("B", "Work", image)];
+
+        write_notes(&notes, &config)?;
+
+        let work = fs::read_to_string(temp_dir.path().join("Work.md"))?;
+        let a = format!("attachment-{}-001.png", short_id("id-A"));
+        let b = format!("attachment-{}-001.png", short_id("id-B"));
+        assert!(work.contains(&format!("![](attachments/Work/{})", a)));
+        assert!(work.contains(&format!("![](attachments/Work/{})", b)));
+        let attachments = temp_dir.path().join("attachments").join("Work");
+        assert!(attachments.join(a).exists());
+        assert!(attachments.join(b).exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_file_per_folder_keeps_same_named_folders_apart() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let config = ExportConfig {
+            output_dir: temp_dir.path().to_path_buf(),
+            file_per_folder: true,
+            ..ExportConfig::default()
+        };
+        let on_my_mac = Note {
+            account: String::from("On My Mac"),
+            ..note("Local", "Notes", "<p>Local note</p>")
+        };
+        let notes = vec![
+            note("Synced", "Notes", "<p>Synced note</p>"),
+            on_my_mac,
+            note("Plan", "Work/Q1", "<p>Slashed</p>"),
+            note("Budget", "Work-Q1", "<p>Dashed</p>"),
+        ];
+
+        let report = write_notes(&notes, &config)?;
+        let expected = vec![
+            temp_dir.path().join("Notes.md"),
+            temp_dir
+                .path()
+                .join(format!("Notes-{}.md", short_id("On My Mac/Notes"))),
+            temp_dir.path().join("Work-Q1.md"),
+            temp_dir
+                .path()
+                .join(format!("Work-Q1-{}.md", short_id("iCloud/Work-Q1"))),
+        ];
+        assert_eq!(report.written, expected);
+
+        let contents = expected
+            .iter()
+            .map(fs::read_to_string)
+            .collect::<std::io::Result<Vec<_>>>()?;
+        for (content, text) in
+            contents
+                .iter()
+                .zip(["Synced note", "Local note", "Slashed", "Dashed"])
+        {
+            assert!(content.contains(text));
+            assert!(content.contains("notes: 1\n"));
+        }
+
+        // Merging accounts puts both "Notes" folders into one file
+        let merged = ExportConfig {
+            merge_accounts: true,
+            ..config.clone()
+        };
+        let report = write_notes(&notes[..2], &merged)?;
+        assert_eq!(report.written, vec![temp_dir.path().join("Notes.md")]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_file_per_folder_demotes_setext_headings() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let config = ExportConfig {
+            output_dir: temp_dir.path().to_path_buf(),
+            file_per_folder: true,
+            ..ExportConfig::default()
+        };
+        let notes = vec![note(
+            "Plan",
+            "Work",
+            "<h1>Goals</h1><p>Ship</p><h2>Risks</h2><p>Delays</p>",
+        )];
+
+        write_notes(&notes, &config)?;
+
+        let work = fs::read_to_string(temp_dir.path().join("Work.md"))?;
+        assert!(work.contains("\n## Plan\n"));
+        assert!(work.contains("\n### Goals\n"));
+        assert!(work.contains("\n#### Risks\n"));
+        assert!(!work.contains("Risks\n-"));
+
+        let fenced = "```\n# not a heading\n```";
+        assert_eq!(demote_headings(fenced), fenced);
+
+        Ok(())
+    }
+}
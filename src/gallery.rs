@@ -0,0 +1,141 @@
+//! Static HTML gallery of all images extracted during an export.
+//!
+//! Every image is shown as a thumbnail that links to the full image, captioned with
+//! a link to the note it came from. All links are relative to the gallery file, so
+//! the gallery keeps working when the export is moved.
+
+use crate::{relative_link, ExportedAttachment};
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::Path;
+
+/// Renders the gallery page for images linked relative to `gallery_dir`.
+pub fn render_gallery(attachments: &[ExportedAttachment], gallery_dir: &Path) -> String {
+    let mut html = String::new();
+    html.push_str("<!DOCTYPE html>\n<html>\n<head>\n");
+    html.push_str("<meta charset=\"utf-8\">\n");
+    html.push_str("<title>Apple Notes images</title>\n");
+    html.push_str("<style>\n");
+    html.push_str("body { font-family: sans-serif; }\n");
+    html.push_str(".gallery { display: flex; flex-wrap: wrap; gap: 1em; }\n");
+    html.push_str("figure { margin: 0; width: 200px; }\n");
+    html.push_str("figure img { max-width: 200px; max-height: 200px; }\n");
+    html.push_str("</style>\n");
+    html.push_str("</head>\n<body>\n");
+    html.push_str(&format!("<h1>{} images</h1>\n", attachments.len()));
+    html.push_str("<div class=\"gallery\">\n");
+
+    for attachment in attachments {
+        let image = escape_html(&relative_link(gallery_dir, &attachment.path));
+        let note = escape_html(&relative_link(gallery_dir, &attachment.note_path));
+        let title = escape_html(&attachment.note_title);
+        html.push_str(&format!(
+            "<figure><a href=\"{image}\"><img src=\"{image}\" alt=\"{title}\" loading=\"lazy\"></a>\
+             <figcaption><a href=\"{note}\">{title}</a></figcaption></figure>\n"
+        ));
+    }
+
+    html.push_str("</div>\n</body>\n</html>\n");
+    html
+}
+
+/// Writes the gallery page for the given images to `path`.
+///
+/// # Errors
+/// * If the gallery file cannot be written
+pub fn write_gallery(attachments: &[ExportedAttachment], path: &Path) -> Result<()> {
+    let gallery_dir = path.parent().unwrap_or(Path::new(""));
+    fs::create_dir_all(gallery_dir)
+        .with_context(|| format!("Failed to create directory: {:?}", gallery_dir))?;
+
+    fs::write(path, render_gallery(attachments, gallery_dir).as_bytes())
+        .with_context(|| format!("Failed to write gallery file: {:?}", path))?;
+
+    Ok(())
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{short_id, write_notes, ExportConfig, Note};
+    use tempfile::tempdir;
+
+    const PIXEL: &str = "iVBORw0KGgoAAAANSUhEUgAAAAEAAAABCAQAAAC1HAwCAAAAC0lEQVR42mNk+A8AAQUBAScY42YAAAAASUVORK5CYII=";
+
+    #[test]
+    fn test_gallery_references_all_images() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let gallery_path = temp_dir.path().join("gallery.html");
+        let config = ExportConfig {
+            output_dir: temp_dir.path().to_path_buf(),
+            gallery: Some(gallery_path.clone()),
+            ..ExportConfig::default()
+        };
+        let note = |title: &str, folder: &str, format: &str| Note {
+            title: String::from(title),
+            content: format!(r#"<img src="data:image/{};base64,{}"/>"#, format, PIXEL),
+            folder: String::from(folder),
+            account: String::from("iCloud"),
+            id: format!("id-{}", title),
+            created: String::from("2024-01-01"),
+            modified: String::from("2024-01-01"),
+            flagged: false,
+            has_reminder: false,
+        };
+
+        write_notes(
+            &[note("Cat", "Pets", "png"), note("Beach", "Trips", "jpeg")],
+            &config,
+        )?;
+
+        let gallery = fs::read_to_string(gallery_path)?;
+        assert!(gallery.contains(&format!(
+            "<img src=\"Pets/attachments/attachment-{}-001.png\"",
+            short_id("id-Cat")
+        )));
+        assert!(gallery.contains("<a href=\"Pets/Cat.md\">Cat</a>"));
+        assert!(gallery.contains(&format!(
+            "<img src=\"Trips/attachments/attachment-{}-001.jpeg\"",
+            short_id("id-Beach")
+        )));
+        assert!(gallery.contains("<a href=\"Trips/Beach.md\">Beach</a>"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_gallery_in_current_dir_links_absolute_export() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let note_path = temp_dir.path().join("Pets").join("Cat.md");
+        let attachments = [ExportedAttachment {
+            path: temp_dir
+                .path()
+                .join("Pets")
+                .join("attachments")
+                .join("cat.png"),
+            note_title: String::from("Cat"),
+            note_path: note_path.clone(),
+        }];
+
+        // `--gallery gallery.html` with an absolute `--output`
+        let gallery = render_gallery(&attachments, Path::new(""));
+
+        let href = gallery
+            .split("<a href=\"")
+            .nth(2)
+            .and_then(|rest| rest.split('"').next())
+            .unwrap();
+        assert!(!href.starts_with('/'));
+        let cwd = std::env::current_dir()?;
+        assert_eq!(crate::absolute_path(&cwd.join(href)), note_path);
+
+        Ok(())
+    }
+}
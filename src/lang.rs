@@ -0,0 +1,127 @@
+//! Lightweight language detection for the frontmatter `lang` field.
+//!
+//! Detection counts common function words of a handful of European languages and
+//! picks the language with the clearest lead. It needs no model data, which keeps
+//! the feature cheap, but it only knows the languages listed in `STOPWORDS` and
+//! gives up on notes that are too short to tell.
+
+/// Minimum number of words a note needs before detection is attempted.
+const MIN_WORDS: usize = 8;
+
+/// Minimum number of stopword hits the detected language needs.
+const MIN_HITS: usize = 3;
+
+/// Common function words per ISO 639-1 language code.
+const STOPWORDS: &[(&str, &[&str])] = &[
+    (
+        "en",
+        &[
+            "the", "and", "of", "to", "is", "in", "that", "it", "was", "for", "with", "this",
+            "are", "have", "not", "be", "you", "on", "at", "but",
+        ],
+    ),
+    (
+        "fr",
+        &[
+            "le", "la", "les", "de", "et", "est", "un", "une", "des", "du", "que", "qui", "dans",
+            "pour", "pas", "sur", "avec", "ce", "sont", "nous", "mais",
+        ],
+    ),
+    (
+        "de",
+        &[
+            "der", "die", "das", "und", "ist", "nicht", "ein", "eine", "zu", "mit", "den", "von",
+            "auf", "sich", "ich", "auch", "es", "dem", "wir", "sind",
+        ],
+    ),
+    (
+        "es",
+        &[
+            "el", "los", "las", "y", "es", "que", "del", "una", "por", "con", "para", "como",
+            "pero", "su", "se", "muy", "está", "son", "lo", "al",
+        ],
+    ),
+    (
+        "it",
+        &[
+            "il", "gli", "e", "che", "di", "della", "una", "per", "non", "sono", "con", "del",
+            "ma", "anche", "questo", "nel", "alla", "lo", "è", "come",
+        ],
+    ),
+    (
+        "pt",
+        &[
+            "o", "os", "as", "e", "que", "do", "da", "uma", "para", "com", "não", "em", "dos",
+            "mais", "mas", "como", "ao", "foi", "são", "seu",
+        ],
+    ),
+    (
+        "nl",
+        &[
+            "de", "het", "een", "en", "van", "is", "dat", "niet", "op", "te", "zijn", "met",
+            "voor", "ook", "maar", "wij", "er", "aan", "bij", "naar",
+        ],
+    ),
+];
+
+/// Detects the language of a plain text, returning its ISO 639-1 code.
+///
+/// Returns `None` for short texts and when no language clearly wins.
+pub fn detect_language(text: &str) -> Option<&'static str> {
+    let words: Vec<String> = text
+        .split(|c: char| !c.is_alphabetic())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_lowercase())
+        .collect();
+    if words.len() < MIN_WORDS {
+        return None;
+    }
+
+    let mut scores: Vec<(&'static str, usize)> = STOPWORDS
+        .iter()
+        .map(|(code, stopwords)| {
+            let hits = words
+                .iter()
+                .filter(|word| stopwords.contains(&word.as_str()))
+                .count();
+            (*code, hits)
+        })
+        .collect();
+    scores.sort_by_key(|(_, hits)| std::cmp::Reverse(*hits));
+
+    let (code, best) = scores[0];
+    let runner_up = scores[1].1;
+    if best >= MIN_HITS && best > runner_up {
+        Some(code)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_english_and_french() {
+        assert_eq!(
+            detect_language(
+                "The meeting is at noon and the agenda for this week is in the shared folder."
+            ),
+            Some("en")
+        );
+        assert_eq!(
+            detect_language(
+                "La réunion est à midi et les notes de la semaine sont dans le dossier partagé."
+            ),
+            Some("fr")
+        );
+    }
+
+    #[test]
+    fn test_detect_short_text_is_none() {
+        assert_eq!(detect_language(""), None);
+        assert_eq!(detect_language("Groceries"), None);
+        assert_eq!(detect_language("milk eggs bread"), None);
+    }
+}
@@ -20,9 +20,22 @@ use anyhow::{anyhow, Context, Result};
 use base64::{engine::general_purpose::STANDARD as base64, Engine as _};
 use scraper::{Html, Selector};
 use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::ffi::OsStr;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Component, Path, PathBuf};
 use std::process::Command;
+use std::time::Duration;
+
+pub mod dedup;
+pub mod diagram;
+pub mod folder_file;
+pub mod gallery;
+#[cfg(feature = "lang-detect")]
+pub mod lang;
+pub mod markdown;
+pub mod outline;
+pub mod prune;
 
 /// Represents a single Apple Note with its metadata and content.
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -41,6 +54,17 @@ pub struct Note {
     pub created: String,
     /// Last modification date as a string
     pub modified: String,
+    /// Whether the note is flagged
+    ///
+    /// The Notes scripting dictionary exposes neither this nor `has_reminder`, so
+    /// `export-notes.applescript` cannot emit them and notes from [`get_notes`] always
+    /// have both `false`. They are only set for notes from other sources that are
+    /// passed to [`write_notes`], and are written to the frontmatter when `true`.
+    #[serde(default)]
+    pub flagged: bool,
+    /// Whether the note has a linked reminder (see `flagged`)
+    #[serde(default)]
+    pub has_reminder: bool,
 }
 
 /// Configuration options for the export process.
@@ -50,14 +74,56 @@ pub struct ExportConfig {
     pub output_dir: PathBuf,
     /// Whether to store images in a separate attachments folder
     pub use_attachments: bool,
-    /// Format string for filenames (supports &title, &folder, &account, &id)
+    /// Format string for filenames (supports &title, &folder, &account, &id, &shortid)
     pub filename_format: String,
-    /// Format string for subdirectories (supports &title, &folder, &account, &id)
+    /// Format string for subdirectories (supports &title, &folder, &account, &id, &shortid);
+    /// `/` separates nested directories
     pub subdir_format: String,
     /// Whether to organize notes in subdirectories
     pub use_subdirs: bool,
     /// Whether to save HTML files alongside Markdown (for debugging)
     pub save_html: bool,
+    /// Optional path for an outline of the exported notes (OPML, or JSON for `.json` paths)
+    pub outline_output: Option<PathBuf>,
+    /// Whether to skip notes whose plain text matches a more recently modified note
+    pub dedup_by_content: bool,
+    /// Whether to write a pointer file linking to the kept note for each skipped duplicate
+    pub dedup_pointer_files: bool,
+    /// Whether `&id` in format strings renders the short id instead of the full id
+    pub shorten_ids: bool,
+    /// Whether to write one Markdown file per folder containing all of its notes
+    pub file_per_folder: bool,
+    /// Path of the `osascript` binary; falls back to `NOTES2MD_OSASCRIPT`, then to `PATH`
+    pub osascript_path: Option<PathBuf>,
+    /// Whether to add a detected `lang` to the frontmatter (requires the `lang-detect` feature)
+    pub detect_language: bool,
+    /// Whether to ignore accounts in paths, merging same-named folders of all accounts
+    pub merge_accounts: bool,
+    /// Whether to remove headings without text, such as a lone `##`, from the Markdown
+    pub trim_empty_headings: bool,
+    /// Optional path for an HTML page showing every extracted image
+    pub gallery: Option<PathBuf>,
+    /// Whether to turn superscript references with a trailing numbered list into footnotes
+    pub detect_footnotes: bool,
+    /// Whether to replace invalid UTF-8 in the AppleScript output instead of failing
+    pub lossy_utf8: bool,
+    /// Whether to leave files alone whose frontmatter shows the note is unchanged
+    /// (ignored with `file_per_folder`)
+    pub incremental: bool,
+    /// How often watch mode polls Apple Notes; `None` exports once
+    pub watch_interval: Option<Duration>,
+    /// Optional path for a Mermaid diagram of the account → folder structure
+    /// (wrapped in a Markdown code block for `.md` paths)
+    pub emit_structure_diagram: Option<PathBuf>,
+    /// Optional maximum length, in characters, of exported note paths; longer folder
+    /// names and filenames are shortened to fit
+    pub max_path_length: Option<usize>,
+    /// Whether to remove exported files of notes deleted from Apple Notes, and the old
+    /// files of renamed or moved notes (only with `incremental`, ignored with
+    /// `file_per_folder`)
+    pub prune_deleted: bool,
+    /// Whether `prune_deleted` only reports the files it would remove
+    pub prune_dry_run: bool,
 }
 
 impl Default for ExportConfig {
@@ -69,10 +135,196 @@ impl Default for ExportConfig {
             subdir_format: String::from("&folder"),
             use_subdirs: true,
             save_html: false,
+            outline_output: None,
+            dedup_by_content: false,
+            dedup_pointer_files: false,
+            shorten_ids: false,
+            file_per_folder: false,
+            osascript_path: None,
+            detect_language: false,
+            merge_accounts: false,
+            trim_empty_headings: false,
+            gallery: None,
+            detect_footnotes: false,
+            lossy_utf8: false,
+            incremental: false,
+            watch_interval: None,
+            emit_structure_diagram: None,
+            max_path_length: None,
+            prune_deleted: false,
+            prune_dry_run: false,
+        }
+    }
+}
+
+/// Notes retrieved from Apple Notes, with problems that did not stop the retrieval.
+#[derive(Debug, Clone, Default)]
+pub struct FetchedNotes {
+    /// The retrieved notes
+    pub notes: Vec<Note>,
+    /// Human-readable descriptions of recovered problems
+    pub warnings: Vec<String>,
+}
+
+/// Summary of what an export wrote and skipped.
+#[derive(Debug, Clone, Default)]
+pub struct ExportReport {
+    /// Paths of the Markdown files written
+    pub written: Vec<PathBuf>,
+    /// Paths of the Markdown files left alone because their note is unchanged
+    pub unchanged: Vec<PathBuf>,
+    /// Paths of the pointer files written for skipped duplicates
+    pub pointers: Vec<PathBuf>,
+    /// Files of deleted, renamed or moved notes that were removed (or would be, in
+    /// a dry run)
+    pub pruned: Vec<PathBuf>,
+    /// Notes that were not exported because another note has the same content
+    pub skipped_duplicates: Vec<dedup::SkippedDuplicate>,
+    /// Images extracted from the notes
+    pub attachments: Vec<ExportedAttachment>,
+    /// Human-readable descriptions of steps that were skipped for safety
+    pub warnings: Vec<String>,
+}
+
+/// An image extracted from a note during export.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExportedAttachment {
+    /// Path of the saved image
+    pub path: PathBuf,
+    /// Title of the note the image belongs to
+    pub note_title: String,
+    /// Path of the Markdown file referencing the image
+    pub note_path: PathBuf,
+}
+
+/// Where the images of rendered notes are saved and how they are referenced.
+pub(crate) struct AttachmentTarget {
+    /// Directory the images are written to
+    pub(crate) dir: PathBuf,
+    /// Prefix of the rewritten image references, relative to the Markdown file
+    pub(crate) link_prefix: String,
+    /// Start of the image filenames, unique to the note being rendered
+    pub(crate) name_prefix: String,
+    /// Number of images of the note extracted so far; the next image gets the
+    /// following number
+    pub(crate) counter: usize,
+    /// Images extracted but not yet written, with their decoded bytes
+    pub(crate) pending: Vec<(PathBuf, Vec<u8>)>,
+    /// Limit no image path may exceed, from `config.max_path_length`
+    pub(crate) max_path_length: Option<usize>,
+}
+
+impl AttachmentTarget {
+    /// Creates a target for the images of `note`.
+    pub(crate) fn new(
+        dir: PathBuf,
+        link_prefix: String,
+        note: &Note,
+        config: &ExportConfig,
+    ) -> Self {
+        let mut target = AttachmentTarget {
+            dir,
+            link_prefix,
+            name_prefix: String::new(),
+            counter: 0,
+            pending: Vec::new(),
+            max_path_length: config.max_path_length,
+        };
+        target.start_note(note);
+        target
+    }
+
+    /// Names and numbers the following images after `note`.
+    ///
+    /// Image names only depend on the note's id and the image's position in the note,
+    /// so notes sharing a directory never reuse each other's names, whichever order
+    /// they are exported in and whether or not an incremental export rewrites them.
+    pub(crate) fn start_note(&mut self, note: &Note) {
+        self.name_prefix = format!("attachment-{}-", short_id(&note.id));
+        self.counter = 0;
+    }
+
+    /// Writes the pending images to disk, returning their paths.
+    pub(crate) fn flush(&mut self) -> Result<Vec<PathBuf>> {
+        write_attachments(&self.pending)?;
+        Ok(self.pending.drain(..).map(|(path, _)| path).collect())
+    }
+}
+
+/// A note rendered to memory: everything an export would write for it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InMemoryNote {
+    /// Path the Markdown file would be written to
+    pub path: PathBuf,
+    /// The complete file content, including the frontmatter
+    pub markdown: String,
+    /// Paths and decoded bytes of the note's images
+    pub attachments: Vec<(PathBuf, Vec<u8>)>,
+}
+
+/// Progress of an export, reported after each note is written.
+///
+/// Byte counts refer to the HTML content of the notes, which dominates processing
+/// time, so `bytes_processed / bytes_total` is a better basis for an ETA than the
+/// note count.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress {
+    /// Number of notes processed so far
+    pub notes_done: usize,
+    /// Number of notes that will be processed
+    pub notes_total: usize,
+    /// Sum of the content lengths of the notes processed so far
+    pub bytes_processed: u64,
+    /// Sum of the content lengths of all notes that will be processed
+    pub bytes_total: u64,
+}
+
+impl Progress {
+    /// Returns the processed share of the content bytes, between 0.0 and 1.0.
+    pub fn fraction(&self) -> f64 {
+        if self.bytes_total == 0 {
+            1.0
+        } else {
+            self.bytes_processed as f64 / self.bytes_total as f64
         }
     }
 }
 
+/// Environment variable overriding the `osascript` binary when no path is configured.
+pub const OSASCRIPT_ENV: &str = "NOTES2MD_OSASCRIPT";
+
+/// Output of an external command run through a [`CommandRunner`].
+#[derive(Debug, Clone)]
+pub struct CommandOutput {
+    /// Whether the command exited successfully
+    pub success: bool,
+    /// Everything the command wrote to stdout
+    pub stdout: Vec<u8>,
+    /// Everything the command wrote to stderr
+    pub stderr: Vec<u8>,
+}
+
+/// Runs external commands, so the AppleScript invocation can be replaced in tests.
+pub trait CommandRunner {
+    /// Runs `program` with `args` and waits for it to finish.
+    fn run(&self, program: &Path, args: &[&OsStr]) -> std::io::Result<CommandOutput>;
+}
+
+/// [`CommandRunner`] that spawns real processes.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemRunner;
+
+impl CommandRunner for SystemRunner {
+    fn run(&self, program: &Path, args: &[&OsStr]) -> std::io::Result<CommandOutput> {
+        let output = Command::new(program).args(args).output()?;
+        Ok(CommandOutput {
+            success: output.status.success(),
+            stdout: output.stdout,
+            stderr: output.stderr,
+        })
+    }
+}
+
 /// Exports all notes from Apple Notes to Markdown files.
 ///
 /// This function:
@@ -80,6 +332,7 @@ impl Default for ExportConfig {
 /// 2. Retrieves all notes using AppleScript
 /// 3. Processes each note (converts HTML to Markdown, handles images)
 /// 4. Saves notes with their metadata as Markdown files
+/// 5. Writes the outline file, if one is configured
 ///
 /// # Arguments
 /// * `config` - Configuration options for the export process
@@ -92,31 +345,258 @@ impl Default for ExportConfig {
 /// * If the AppleScript execution fails
 /// * If any note processing or saving fails
 pub fn export_notes(config: &ExportConfig) -> Result<Vec<Note>> {
+    // Get notes data from AppleScript
+    let notes = fetch_notes(config)?.notes;
+
+    write_notes(&notes, config)?;
+
+    Ok(notes)
+}
+
+/// Writes already retrieved notes to the output directory.
+///
+/// This is the part of [`export_notes`] that runs after the notes have been fetched,
+/// which makes it usable with notes obtained from somewhere other than AppleScript.
+///
+/// # Arguments
+/// * `notes` - The notes to export
+/// * `config` - Configuration options for the export process
+///
+/// # Returns
+/// * `Result<ExportReport>` - What was written and skipped on success
+///
+/// # Errors
+/// * If the output directory cannot be created
+/// * If any note processing or saving fails
+/// * If files of deleted notes cannot be pruned
+/// * If the outline cannot be written
+pub fn write_notes(notes: &[Note], config: &ExportConfig) -> Result<ExportReport> {
+    write_notes_with_progress(notes, config, &mut |_| {})
+}
+
+/// Writes notes like [`write_notes`], calling `on_progress` after each note.
+///
+/// # Errors
+/// * Same as [`write_notes`]
+pub fn write_notes_with_progress(
+    notes: &[Note],
+    config: &ExportConfig,
+    on_progress: &mut dyn FnMut(&Progress),
+) -> Result<ExportReport> {
     // Create output directory if it doesn't exist
     fs::create_dir_all(&config.output_dir).context("Failed to create output directory")?;
 
-    // Get notes data from AppleScript
-    let notes = get_notes()?;
+    let mut report = ExportReport::default();
+
+    let prune = config.prune_deleted && config.incremental && !config.file_per_folder;
+    // Taken before writing, to tell whether any previously exported note remains
+    let previous_ids = if prune {
+        prune::exported_note_ids(config)?
+    } else {
+        HashSet::new()
+    };
+
+    // Drop notes with duplicate content, keeping the most recently modified one
+    let notes = if config.dedup_by_content {
+        let (kept, skipped) = dedup::dedup_notes(notes);
+        report.skipped_duplicates = skipped;
+        kept
+    } else {
+        notes.to_vec()
+    };
+
+    let mut progress = Progress {
+        notes_done: 0,
+        notes_total: notes.len(),
+        bytes_processed: 0,
+        bytes_total: notes.iter().map(|note| note.content.len() as u64).sum(),
+    };
+    let mut note_done = |note: &Note| {
+        progress.notes_done += 1;
+        progress.bytes_processed += note.content.len() as u64;
+        on_progress(&progress);
+    };
+
+    if config.file_per_folder {
+        folder_file::write_folder_files(&notes, config, &mut report, &mut note_done)?;
+    } else {
+        // Process each note. Unchanged notes are still rendered so that their images
+        // are reported, e.g. for the gallery.
+        render_note_files(&notes, config, &mut |note, file, up_to_date| {
+            if !up_to_date {
+                write_note_file(&file)?;
+            }
 
-    // Process each note
-    for note in &notes {
-        let markdown = process_note(note, config)?;
-        save_note(note, &markdown, config)?;
+            report
+                .attachments
+                .extend(
+                    file.attachments
+                        .into_iter()
+                        .map(|(image, _)| ExportedAttachment {
+                            path: image,
+                            note_title: note.title.clone(),
+                            note_path: file.path.clone(),
+                        }),
+                );
+            if up_to_date {
+                report.unchanged.push(file.path);
+            } else {
+                report.written.push(file.path);
+            }
+            note_done(note);
+            Ok(())
+        })?;
     }
 
-    Ok(notes)
+    // Pointer files would overwrite the combined folder files
+    if config.dedup_pointer_files && !config.file_per_folder {
+        // Plan the pointers after the kept notes, so they never take a kept note's path
+        let planned: Vec<Note> = notes
+            .iter()
+            .chain(
+                report
+                    .skipped_duplicates
+                    .iter()
+                    .map(|skipped| &skipped.note),
+            )
+            .cloned()
+            .collect();
+        let paths = plan_note_paths(&planned, config)?;
+        let (kept_paths, pointer_paths) = paths.split_at(notes.len());
+
+        for (skipped, pointer_path) in report.skipped_duplicates.iter().zip(pointer_paths) {
+            let kept_index = notes
+                .iter()
+                .position(|note| note.id == skipped.kept.id)
+                .expect("the kept note of a duplicate is exported");
+            dedup::write_pointer_file(skipped, pointer_path, &kept_paths[kept_index], config)?;
+            report.pointers.push(pointer_path.clone());
+        }
+    }
+
+    if prune {
+        prune::prune_deleted(config, &notes, &previous_ids, &mut report)?;
+    }
+
+    if let Some(outline_path) = &config.outline_output {
+        outline::write_outline(&notes, config, outline_path)?;
+    }
+
+    if let Some(gallery_path) = &config.gallery {
+        gallery::write_gallery(&report.attachments, gallery_path)?;
+    }
+
+    if let Some(diagram_path) = &config.emit_structure_diagram {
+        diagram::write_structure_diagram(&notes, config, diagram_path)?;
+    }
+
+    Ok(report)
+}
+
+/// Renders notes to memory instead of writing them to disk.
+///
+/// Each returned [`InMemoryNote`] holds the path, content and images that
+/// [`write_notes`] would write for a note. Content deduplication is applied as
+/// configured. Notes are always rendered to their own files: `file_per_folder` and
+/// `save_html` are ignored, and no outline, gallery or pointer files are produced.
+///
+/// # Errors
+/// * If any note cannot be converted
+pub fn export_to_memory(notes: &[Note], config: &ExportConfig) -> Result<Vec<InMemoryNote>> {
+    let config = ExportConfig {
+        file_per_folder: false,
+        save_html: false,
+        ..config.clone()
+    };
+
+    let notes = if config.dedup_by_content {
+        dedup::dedup_notes(notes).0
+    } else {
+        notes.to_vec()
+    };
+
+    let mut files = Vec::with_capacity(notes.len());
+    render_note_files(&notes, &config, &mut |_, file, _| {
+        files.push(file);
+        Ok(())
+    })?;
+
+    Ok(files)
+}
+
+/// Renders each note to its own file, passing the results to `on_file` in order.
+///
+/// `on_file` is also told whether an incremental export finds the note's file up to
+/// date. The HTML copies of such notes are not rewritten.
+fn render_note_files(
+    notes: &[Note],
+    config: &ExportConfig,
+    on_file: &mut dyn FnMut(&Note, InMemoryNote, bool) -> Result<()>,
+) -> Result<()> {
+    for (note, path) in notes.iter().zip(plan_note_paths(notes, config)?) {
+        let mut target = note_attachment_target(note, config)?;
+        let up_to_date = config.incremental && is_up_to_date(note, &path);
+
+        // Create frontmatter and add the markdown content
+        let mut markdown = frontmatter(note, config);
+        markdown.push_str(&render_markdown(note, config, &mut target, !up_to_date)?);
+
+        on_file(
+            note,
+            InMemoryNote {
+                path,
+                markdown,
+                attachments: target.pending,
+            },
+            up_to_date,
+        )?;
+    }
+
+    Ok(())
 }
 
 /// Retrieves all notes from Apple Notes using AppleScript.
 ///
+/// This uses the default configuration; see [`fetch_notes`] to pick the `osascript`
+/// binary, decode invalid UTF-8 or receive warnings.
+///
 /// # Returns
 /// * `Result<Vec<Note>>` - A vector of all notes on success
 ///
 /// # Errors
+/// * Same as [`fetch_notes`]
+pub fn get_notes() -> Result<Vec<Note>> {
+    Ok(fetch_notes(&ExportConfig::default())?.notes)
+}
+
+/// Retrieves all notes from Apple Notes using AppleScript, as configured.
+///
+/// # Arguments
+/// * `config` - Configuration options, used to locate the `osascript` binary
+///
+/// # Returns
+/// * `Result<FetchedNotes>` - All notes and any warnings on success
+///
+/// # Errors
+/// * If the configured `osascript` binary does not exist or is not executable
 /// * If the AppleScript file is not found
 /// * If the AppleScript execution fails
+/// * If the output is not valid UTF-8 and `config.lossy_utf8` is not set
 /// * If the output cannot be parsed as JSON
-pub fn get_notes() -> Result<Vec<Note>> {
+pub fn fetch_notes(config: &ExportConfig) -> Result<FetchedNotes> {
+    fetch_notes_with_runner(config, &SystemRunner)
+}
+
+/// Retrieves all notes like [`fetch_notes`], running AppleScript through `runner`.
+///
+/// # Errors
+/// * Same as [`fetch_notes`]
+pub fn fetch_notes_with_runner(
+    config: &ExportConfig,
+    runner: &dyn CommandRunner,
+) -> Result<FetchedNotes> {
+    let osascript = resolve_osascript(config)?;
+
     let script_path = PathBuf::from("export-notes.applescript");
     if !script_path.exists() {
         return Err(anyhow!(
@@ -124,25 +604,77 @@ pub fn get_notes() -> Result<Vec<Note>> {
         ));
     }
 
-    let output = Command::new("osascript")
-        .arg(script_path)
-        .output()
-        .context("Failed to execute AppleScript")?;
+    let output = runner
+        .run(&osascript, &[script_path.as_os_str()])
+        .with_context(|| format!("Failed to execute AppleScript with {:?}", osascript))?;
 
-    if !output.status.success() {
+    if !output.success {
         return Err(anyhow!(
             "AppleScript execution failed: {}",
             String::from_utf8_lossy(&output.stderr)
         ));
     }
 
-    let json_str =
-        String::from_utf8(output.stdout).context("Failed to parse AppleScript output as UTF-8")?;
+    let mut warnings = Vec::new();
+    let json_str = match String::from_utf8(output.stdout) {
+        Ok(json_str) => json_str,
+        Err(err) if config.lossy_utf8 => {
+            let json_str = String::from_utf8_lossy(err.as_bytes()).into_owned();
+            warnings.push(format!(
+                "AppleScript output contained invalid UTF-8 (first at byte {}); \
+                 replaced {} character(s) with U+FFFD",
+                err.utf8_error().valid_up_to(),
+                json_str.matches('\u{FFFD}').count()
+            ));
+            json_str
+        }
+        Err(err) => {
+            return Err(err).context(
+                "Failed to parse AppleScript output as UTF-8 (enable lossy_utf8 to replace invalid bytes)",
+            )
+        }
+    };
 
     let notes: Vec<Note> =
         serde_json::from_str(&json_str).context("Failed to parse JSON output from AppleScript")?;
 
-    Ok(notes)
+    Ok(FetchedNotes { notes, warnings })
+}
+
+/// Determines the `osascript` binary to run.
+///
+/// An explicitly configured path wins over the `NOTES2MD_OSASCRIPT` environment
+/// variable; both are checked to exist and be executable. Without either, the bare
+/// `osascript` is looked up on `PATH`.
+fn resolve_osascript(config: &ExportConfig) -> Result<PathBuf> {
+    let configured = config.osascript_path.clone().or_else(|| {
+        std::env::var_os(OSASCRIPT_ENV)
+            .filter(|value| !value.is_empty())
+            .map(PathBuf::from)
+    });
+
+    let Some(path) = configured else {
+        return Ok(PathBuf::from("osascript"));
+    };
+
+    if !path.is_file() {
+        return Err(anyhow!("osascript binary not found: {:?}", path));
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mode = fs::metadata(&path)
+            .with_context(|| format!("Failed to read metadata of {:?}", path))?
+            .permissions()
+            .mode();
+        if mode & 0o111 == 0 {
+            return Err(anyhow!("osascript binary is not executable: {:?}", path));
+        }
+    }
+
+    Ok(path)
 }
 
 /// Processes a single note, converting it to Markdown and handling attachments.
@@ -158,24 +690,69 @@ pub fn get_notes() -> Result<Vec<Note>> {
 /// * If image extraction fails
 /// * If HTML processing fails
 pub fn process_note(note: &Note, config: &ExportConfig) -> Result<String> {
+    let mut target = note_attachment_target(note, config)?;
+    let markdown = render_markdown(note, config, &mut target, true)?;
+    target.flush()?;
+    Ok(markdown)
+}
+
+/// Returns where the images of a note exported to its own file go.
+fn note_attachment_target(note: &Note, config: &ExportConfig) -> Result<AttachmentTarget> {
+    let note_dir = get_note_path(note, config)?;
+    let (dir, link_prefix) = if config.use_attachments {
+        (note_dir.join("attachments"), "attachments/")
+    } else {
+        (note_dir, "")
+    };
+
+    Ok(AttachmentTarget::new(
+        dir,
+        String::from(link_prefix),
+        note,
+        config,
+    ))
+}
+
+/// Converts a note to Markdown, extracting its images into `target`.
+///
+/// The images are only collected; callers write them with [`AttachmentTarget::flush`].
+/// With `config.save_html`, the HTML is saved as well unless `write_html` is false.
+pub(crate) fn render_markdown(
+    note: &Note,
+    config: &ExportConfig,
+    target: &mut AttachmentTarget,
+    write_html: bool,
+) -> Result<String> {
     // Extract images and get updated HTML
-    let html_with_local_images = extract_and_save_images(
-        &note.content,
-        &get_note_path(note, config)?,
-        config.use_attachments,
-    )?;
+    let html_with_local_images = extract_images(&note.content, target)?;
 
     // Save the HTML for investigation (optional)
-    if config.save_html {
+    if config.save_html && write_html {
         save_html(note, &html_with_local_images, config)?;
     }
 
     // Convert to markdown
-    let markdown = html2md::parse_html(&html_with_local_images);
+    let mut markdown = merge_split_h1s(
+        note,
+        &html_with_local_images,
+        html2md::parse_html(&html_with_local_images),
+    );
+
+    if config.trim_empty_headings {
+        markdown = markdown::trim_empty_headings(&markdown);
+    }
 
-    // Handle split h1s if present
+    if config.detect_footnotes {
+        markdown = markdown::convert_footnotes(&markdown);
+    }
+
+    Ok(markdown)
+}
+
+/// Joins the text of all `<h1>`s into a single leading `#` heading.
+fn merge_split_h1s(note: &Note, html: &str, markdown: String) -> String {
     if note.content.contains("<h1>") {
-        let doc = Html::parse_document(&html_with_local_images);
+        let doc = Html::parse_document(html);
         let h1_selector = Selector::parse("h1").unwrap();
         let h1_texts: Vec<String> = doc
             .select(&h1_selector)
@@ -185,7 +762,7 @@ pub fn process_note(note: &Note, config: &ExportConfig) -> Result<String> {
         if !h1_texts.is_empty() {
             let joined_text = h1_texts.join("");
             if !joined_text.trim().is_empty() {
-                return Ok(format!(
+                return format!(
                     "# {}\n\n{}",
                     joined_text.trim(),
                     markdown
@@ -193,140 +770,514 @@ pub fn process_note(note: &Note, config: &ExportConfig) -> Result<String> {
                         .filter(|line| !line.starts_with('#'))
                         .collect::<Vec<_>>()
                         .join("\n")
-                ));
+                );
             }
         }
     }
 
-    Ok(markdown)
+    markdown
+}
+
+/// Expands the placeholders of a format string with the note's metadata.
+///
+/// Supported placeholders are `&title`, `&folder`, `&account`, `&id` and `&shortid`
+/// (see [`short_id`]). With `config.shorten_ids` set, `&id` expands to the short id as
+/// well. With `config.merge_accounts` set, `&account` expands to nothing, so notes of
+/// all accounts share one tree. Path separators inside the substituted values are
+/// replaced with `-`, so only the format string itself can introduce directories.
+pub fn render_format(format: &str, note: &Note, config: &ExportConfig) -> String {
+    let id = if config.shorten_ids {
+        short_id(&note.id)
+    } else {
+        note.id.clone()
+    };
+    let placeholders = [
+        ("&shortid", short_id(&note.id)),
+        ("&title", note.title.clone()),
+        ("&folder", note.folder.clone()),
+        (
+            "&account",
+            if config.merge_accounts {
+                String::new()
+            } else {
+                note.account.clone()
+            },
+        ),
+        ("&id", id),
+    ];
+
+    let mut rendered = String::new();
+    let mut rest = format;
+    while let Some(index) = rest.find('&') {
+        rendered.push_str(&rest[..index]);
+        rest = &rest[index..];
+        match placeholders
+            .iter()
+            .find(|(placeholder, _)| rest.starts_with(placeholder))
+        {
+            Some((placeholder, value)) => {
+                rendered.push_str(&value.replace(['/', '\\'], "-"));
+                rest = &rest[placeholder.len()..];
+            }
+            None => {
+                rendered.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    rendered.push_str(rest);
+
+    rendered
+}
+
+/// Returns a stable, 8 character base36 token derived from a note id.
+///
+/// Apple Notes ids are long `x-coredata://` URIs; this keeps filenames short while
+/// still telling notes with the same title apart.
+pub fn short_id(id: &str) -> String {
+    const ALPHABET: &[u8] = b"0123456789abcdefghijklmnopqrstuvwxyz";
+    const LENGTH: usize = 8;
+
+    let mut hash = fnv1a(id.as_bytes());
+    let mut token = vec![b'0'; LENGTH];
+    for slot in token.iter_mut().rev() {
+        *slot = ALPHABET[(hash % 36) as usize];
+        hash /= 36;
+    }
+
+    String::from_utf8(token).expect("base36 alphabet is ASCII")
 }
 
 fn get_note_path(note: &Note, config: &ExportConfig) -> Result<PathBuf> {
     let mut path = config.output_dir.clone();
 
     if config.use_subdirs {
-        path = path.join(&note.folder);
+        let subdir = render_format(&config.subdir_format, note, config);
+        let mut components: Vec<String> = subdir
+            .split('/')
+            .filter(|c| !c.is_empty())
+            .map(avoid_reserved_name)
+            .collect();
+        if let Some(max) = config.max_path_length {
+            fit_components(&config.output_dir, &mut components, max)?;
+        }
+        for component in components {
+            path = path.join(component);
+        }
     }
 
     Ok(path)
 }
 
-fn save_note(note: &Note, markdown: &str, config: &ExportConfig) -> Result<()> {
-    let mut output_path = get_note_path(note, config)?;
-    fs::create_dir_all(&output_path)
-        .with_context(|| format!("Failed to create directory: {:?}", output_path))?;
-
-    // Create filename from title (sanitize it)
-    let safe_title = note
-        .title
-        .replace(|c: char| !c.is_alphanumeric() && c != '-', "-");
-    output_path = output_path.join(format!("{}.md", safe_title));
+/// Room kept in a note's directory for the files written into it, the longest being
+/// an image like `attachments/attachment-0a1b2c3d-001.jpeg`, including the separator
+/// before it. Images with longer names, from a note with more than 999 images or
+/// with a longer extension, are rejected if they would exceed the limit.
+const RESERVED_FILE_LENGTH: usize = 41;
 
-    // Create frontmatter
-    let mut content = String::new();
-    content.push_str("---\n");
-    content.push_str(&format!("title: \"{}\"\n", note.title));
-    content.push_str(&format!("folder: \"{}\"\n", note.folder));
-    content.push_str(&format!("account: \"{}\"\n", note.account));
-    content.push_str(&format!("id: \"{}\"\n", note.id));
-    content.push_str(&format!("created: \"{}\"\n", note.created));
-    content.push_str(&format!("modified: \"{}\"\n", note.modified));
-    content.push_str("---\n\n");
+/// Length of a shortened folder name: one character of the name, `-` and a short id.
+const MIN_COMPONENT_LENGTH: usize = 10;
 
-    // Add the markdown content
-    content.push_str(markdown);
+/// Shortens directory components until `base` joined with them leaves room under
+/// `max` for the files of a note.
+///
+/// The longest component is cut first. A cut component keeps the start of its name
+/// followed by the short id of the full name, so folders that share a long prefix
+/// still end up in different directories.
+///
+/// # Errors
+/// * If the components cannot be shortened enough, e.g. because the base directory
+///   alone is too long
+fn fit_components(base: &Path, components: &mut [String], max: usize) -> Result<()> {
+    loop {
+        let length = path_length(base)
+            + components
+                .iter()
+                .map(|c| 1 + c.chars().count())
+                .sum::<usize>()
+            + RESERVED_FILE_LENGTH;
+        if length <= max {
+            return Ok(());
+        }
 
-    // Write the complete content
-    fs::write(&output_path, content.as_bytes())
-        .with_context(|| format!("Failed to write file: {:?}", output_path))?;
+        let Some(longest) = components
+            .iter_mut()
+            .filter(|c| c.chars().count() > MIN_COMPONENT_LENGTH)
+            .max_by_key(|c| c.chars().count())
+        else {
+            let shortest: PathBuf = components.iter().fold(base.to_path_buf(), |p, c| p.join(c));
+            return Err(anyhow!(
+                "Note paths cannot be kept within the max_path_length of {}: the shortest \
+                 note directory is {:?}, which needs {} characters with room for its files",
+                max,
+                shortest,
+                length
+            ));
+        };
 
-    Ok(())
+        let target = longest
+            .chars()
+            .count()
+            .saturating_sub(length - max)
+            .max(MIN_COMPONENT_LENGTH);
+        let suffix = format!("-{}", short_id(longest));
+        let prefix: String = longest.chars().take(target - suffix.len()).collect();
+        *longest = format!("{}{}", prefix, suffix);
+    }
 }
 
-fn save_html(note: &Note, html: &str, config: &ExportConfig) -> Result<()> {
-    let mut output_path = get_note_path(note, config)?;
-    fs::create_dir_all(&output_path)
-        .with_context(|| format!("Failed to create directory: {:?}", output_path))?;
-
-    // Create filename from title (sanitize it)
-    let safe_title = note
-        .title
-        .replace(|c: char| !c.is_alphanumeric() && c != '-', "-");
-    output_path = output_path.join(format!("{}.html", safe_title));
-
-    // Write the HTML content
-    fs::write(&output_path, html.as_bytes())
-        .with_context(|| format!("Failed to write HTML file: {:?}", output_path))?;
+/// Number of characters of a path, the unit path length limits are given in.
+fn path_length(path: &Path) -> usize {
+    path.as_os_str().to_string_lossy().chars().count()
+}
 
-    Ok(())
+/// Returns the sanitized filename (without extension) of a note.
+fn note_file_stem(note: &Note, config: &ExportConfig) -> String {
+    sanitize_filename(&render_format(&config.filename_format, note, config))
 }
 
-fn extract_and_save_images(
-    html_content: &str,
-    output_dir: &PathBuf,
-    use_attachments: bool,
-) -> Result<String> {
-    let document = Html::parse_document(html_content);
-    let img_selector = Selector::parse("img").unwrap();
-    let mut modified_html = html_content.to_string();
-    let mut img_counter = 0;
+/// Returns the name of a note's Markdown file in `dir`, optionally with the note's
+/// short id appended to tell it apart from a note with the same name.
+///
+/// If `config.max_path_length` is exceeded, the name is cut and ends in the short id
+/// instead, which keeps it unique. The limit also applies to the `.html` copy written
+/// with `save_html`.
+///
+/// # Errors
+/// * If even a name of just the short id would exceed `config.max_path_length`
+fn note_file_name(note: &Note, config: &ExportConfig, dir: &Path, with_id: bool) -> Result<String> {
+    let mut stem = note_file_stem(note, config);
+    if with_id {
+        stem = format!("{}-{}", stem, short_id(&note.id));
+    }
+    let Some(max) = config.max_path_length else {
+        return Ok(format!("{}.md", stem));
+    };
+
+    let extension = if config.save_html { ".html" } else { ".md" };
+    let length = |stem: &str| path_length(dir) + 1 + stem.chars().count() + extension.len();
+    if length(&stem) <= max {
+        return Ok(format!("{}.md", stem));
+    }
 
-    // Determine attachments directory
-    let attachments_dir = if use_attachments {
-        output_dir.join("attachments")
+    let id = short_id(&note.id);
+    let available = max.saturating_sub(length(&id) + 1);
+    let prefix: String = stem.chars().take(available).collect();
+    let stem = if prefix.is_empty() {
+        id
     } else {
-        output_dir.to_owned()
+        format!("{}-{}", prefix, id)
     };
-
-    // Create attachments directory if it doesn't exist and we're using it
-    if use_attachments {
-        fs::create_dir_all(&attachments_dir).with_context(|| {
-            format!(
-                "Failed to create attachments directory: {:?}",
-                attachments_dir
-            )
-        })?;
+    if length(&stem) > max {
+        return Err(anyhow!(
+            "Note files cannot be kept within the max_path_length of {}: the shortest \
+             path is {:?} ({} characters)",
+            max,
+            dir.join(format!("{}{}", stem, extension)),
+            length(&stem)
+        ));
     }
+    Ok(format!("{}.md", stem))
+}
 
-    // Find all img tags
-    for img in document.select(&img_selector) {
-        if let Some(src) = img.value().attr("src") {
-            if src.starts_with("data:image") {
-                img_counter += 1;
+/// Returns the path of the Markdown file a note is saved to.
+///
+/// Combined folder files are not taken into account, see [`plan_note_paths`].
+pub(crate) fn note_file_path(note: &Note, config: &ExportConfig) -> Result<PathBuf> {
+    let dir = get_note_path(note, config)?;
+    let name = note_file_name(note, config, &dir, false)?;
+    Ok(dir.join(name))
+}
 
-                // Extract image format and data
-                let parts: Vec<&str> = src.split(',').collect();
-                if parts.len() != 2 {
-                    continue; // Skip malformed data URLs
-                }
+/// Assigns every note the path of its Markdown file.
+///
+/// Notes that would end up at the same path (compared case-insensitively, like the
+/// default macOS filesystem does) are kept apart: the first one keeps its plain name
+/// and later ones get their short id appended. With `file_per_folder`, every note is
+/// assigned the combined file of its folder.
+pub(crate) fn plan_note_paths(notes: &[Note], config: &ExportConfig) -> Result<Vec<PathBuf>> {
+    if config.file_per_folder {
+        let folders = folder_file::plan_folder_files(notes, config);
+        return Ok(notes
+            .iter()
+            .map(|note| {
+                folders
+                    .iter()
+                    .find(|f| f.contains(note, config))
+                    .expect("every note is planned into a folder file")
+                    .path(config)
+            })
+            .collect());
+    }
 
-                // Get format from header (e.g., "data:image/jpeg;base64" -> "jpeg")
-                let format = parts[0]
-                    .split('/')
-                    .nth(1)
-                    .and_then(|s| s.split(';').next())
-                    .unwrap_or("png");
+    let mut used = HashSet::new();
+    let mut paths = Vec::with_capacity(notes.len());
 
-                // Decode base64 data
-                let image_data = base64
-                    .decode(parts[1])
-                    .with_context(|| "Failed to decode base64 image data")?;
+    for note in notes {
+        let mut path = note_file_path(note, config)?;
+        if !used.insert(path.to_string_lossy().to_lowercase()) {
+            let dir = path.parent().unwrap_or(Path::new("")).to_path_buf();
+            path = dir.join(note_file_name(note, config, &dir, true)?);
+            used.insert(path.to_string_lossy().to_lowercase());
+        }
+        paths.push(path);
+    }
 
-                // Generate filename
-                let filename = format!("attachment-{:03}.{}", img_counter, format);
-                let image_path = attachments_dir.join(&filename);
+    Ok(paths)
+}
 
-                // Save the image
-                fs::write(&image_path, image_data)
-                    .with_context(|| format!("Failed to write image file: {:?}", image_path))?;
+/// Builds a `/`-separated link to `target` relative to the directory `from`.
+///
+/// Either path may be relative to the current directory, so both are made absolute
+/// before they are compared.
+pub(crate) fn relative_link(from: &Path, target: &Path) -> String {
+    let from = absolute_path(from);
+    let target = absolute_path(target);
+    let from: Vec<Component> = from.components().collect();
+    let target: Vec<Component> = target.components().collect();
+    let common = from.iter().zip(&target).take_while(|(a, b)| a == b).count();
 
-                // Update HTML to reference the local file
-                let new_src = if use_attachments {
-                    format!("attachments/{}", filename)
-                } else {
-                    filename
-                };
+    let mut parts: Vec<String> = vec![String::from(".."); from.len() - common];
+    parts.extend(
+        target[common..]
+            .iter()
+            .map(|c| c.as_os_str().to_string_lossy().into_owned()),
+    );
+    parts.join("/")
+}
+
+/// Joins a relative `path` onto the current directory and resolves `.` and `..`
+/// components without touching the filesystem.
+pub(crate) fn absolute_path(path: &Path) -> PathBuf {
+    let joined = if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        std::env::current_dir().unwrap_or_default().join(path)
+    };
+
+    let mut absolute = PathBuf::new();
+    for component in joined.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                absolute.pop();
+            }
+            other => absolute.push(other),
+        }
+    }
+    absolute
+}
+
+/// 64-bit FNV-1a hash, used where a hash has to stay stable across runs and builds.
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in bytes {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+/// Replaces every character that is not alphanumeric or `-` with `-`.
+///
+/// Names reserved on Windows get an underscore appended, see [`avoid_reserved_name`].
+pub(crate) fn sanitize_filename(name: &str) -> String {
+    avoid_reserved_name(&name.replace(|c: char| !c.is_alphanumeric() && c != '-', "-"))
+}
+
+/// Device names that Windows reserves in every directory, whatever the extension.
+const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Appends `_` to the stem of a file or directory name that Windows reserves.
+///
+/// Windows refuses names like `con` or `NUL.md` even though macOS allows them, so
+/// this keeps exports portable. The stem is everything before the first `.` and is
+/// compared case-insensitively; other names are returned unchanged.
+pub(crate) fn avoid_reserved_name(name: &str) -> String {
+    let (stem, extension) = match name.find('.') {
+        Some(dot) => name.split_at(dot),
+        None => (name, ""),
+    };
+    let reserved = RESERVED_NAMES
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(stem.trim_end()));
+
+    if reserved {
+        format!("{}_{}", stem, extension)
+    } else {
+        name.to_string()
+    }
+}
+
+/// Reduces HTML to its text, with all runs of whitespace collapsed to single spaces.
+pub(crate) fn plain_text(html: &str) -> String {
+    let document = Html::parse_document(html);
+    let text = document.root_element().text().collect::<Vec<_>>().join(" ");
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Builds the YAML frontmatter block for a note.
+pub(crate) fn frontmatter(note: &Note, config: &ExportConfig) -> String {
+    let mut content = String::new();
+    content.push_str("---\n");
+    content.push_str(&format!("title: \"{}\"\n", note.title));
+    content.push_str(&format!("folder: \"{}\"\n", note.folder));
+    content.push_str(&format!("account: \"{}\"\n", note.account));
+    content.push_str(&format!("id: \"{}\"\n", note.id));
+    content.push_str(&format!("created: \"{}\"\n", note.created));
+    content.push_str(&format!("modified: \"{}\"\n", note.modified));
+    if note.flagged {
+        content.push_str("flagged: true\n");
+    }
+    if note.has_reminder {
+        content.push_str("has_reminder: true\n");
+    }
+    #[cfg(feature = "lang-detect")]
+    if config.detect_language {
+        if let Some(lang) = lang::detect_language(&plain_text(&note.content)) {
+            content.push_str(&format!("lang: {}\n", lang));
+        }
+    }
+    #[cfg(not(feature = "lang-detect"))]
+    let _ = config;
+    content.push_str("---\n\n");
+    content
+}
+
+/// Reads a top-level value from the frontmatter of an exported Markdown file.
+///
+/// Surrounding quotes are removed. Returns `None` if the file has no frontmatter or
+/// the frontmatter has no such key.
+pub(crate) fn frontmatter_value(markdown: &str, key: &str) -> Option<String> {
+    let rest = markdown.strip_prefix("---\n")?;
+    let end = rest.find("\n---")?;
+    rest[..end].lines().find_map(|line| {
+        let value = line.strip_prefix(key)?.strip_prefix(':')?.trim();
+        let value = value
+            .strip_prefix('"')
+            .and_then(|v| v.strip_suffix('"'))
+            .unwrap_or(value);
+        Some(value.to_string())
+    })
+}
+
+/// Whether the file at `path` was exported from the current version of `note`.
+///
+/// Apple Notes bumps a note's modification date on every edit, so matching `id` and
+/// `modified` frontmatter values mean the file is up to date.
+fn is_up_to_date(note: &Note, path: &Path) -> bool {
+    let Ok(existing) = fs::read_to_string(path) else {
+        return false;
+    };
+    frontmatter_value(&existing, "id").as_deref() == Some(note.id.as_str())
+        && frontmatter_value(&existing, "modified").as_deref() == Some(note.modified.as_str())
+}
+
+/// Writes a rendered note and its images to disk.
+fn write_note_file(file: &InMemoryNote) -> Result<()> {
+    if let Some(parent) = file.path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory: {:?}", parent))?;
+    }
+
+    // Write the complete content
+    fs::write(&file.path, file.markdown.as_bytes())
+        .with_context(|| format!("Failed to write file: {:?}", file.path))?;
+
+    write_attachments(&file.attachments)
+}
+
+/// Writes extracted images, creating their directories as needed.
+fn write_attachments(attachments: &[(PathBuf, Vec<u8>)]) -> Result<()> {
+    for (image_path, image_data) in attachments {
+        if let Some(dir) = image_path.parent() {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create attachments directory: {:?}", dir))?;
+        }
+
+        fs::write(image_path, image_data)
+            .with_context(|| format!("Failed to write image file: {:?}", image_path))?;
+    }
+
+    Ok(())
+}
+
+fn save_html(note: &Note, html: &str, config: &ExportConfig) -> Result<()> {
+    let mut output_path = get_note_path(note, config)?;
+    fs::create_dir_all(&output_path)
+        .with_context(|| format!("Failed to create directory: {:?}", output_path))?;
+
+    let name = note_file_name(note, config, &output_path, false)?;
+    output_path = output_path.join(Path::new(&name).with_extension("html"));
+
+    // Write the HTML content
+    fs::write(&output_path, html.as_bytes())
+        .with_context(|| format!("Failed to write HTML file: {:?}", output_path))?;
+
+    Ok(())
+}
+
+/// Collects the embedded images of `html_content` into `target` and rewrites their
+/// references. Images are named with `target.name_prefix` and numbered after
+/// `target.counter`, which is advanced for every image extracted.
+fn extract_images(html_content: &str, target: &mut AttachmentTarget) -> Result<String> {
+    let document = Html::parse_document(html_content);
+    let img_selector = Selector::parse("img").unwrap();
+    let mut modified_html = html_content.to_string();
+    let mut seen_sources = HashSet::new();
+
+    // Find all img tags
+    for img in document.select(&img_selector) {
+        if let Some(src) = img.value().attr("src") {
+            if src.starts_with("data:image") {
+                // Every occurrence of a source is rewritten the first time it is seen,
+                // so repeated references share one file
+                if !seen_sources.insert(src) {
+                    continue;
+                }
+
+                target.counter += 1;
 
+                // Extract image format and data
+                let parts: Vec<&str> = src.split(',').collect();
+                if parts.len() != 2 {
+                    continue; // Skip malformed data URLs
+                }
+
+                // Get format from header (e.g., "data:image/jpeg;base64" -> "jpeg")
+                let format = parts[0]
+                    .split('/')
+                    .nth(1)
+                    .and_then(|s| s.split(';').next())
+                    .unwrap_or("png");
+
+                // Decode base64 data
+                let image_data = base64
+                    .decode(parts[1])
+                    .with_context(|| "Failed to decode base64 image data")?;
+
+                // Generate filename
+                let filename = format!("{}{:03}.{}", target.name_prefix, target.counter, format);
+                let image_path = target.dir.join(&filename);
+                if let Some(max) = target.max_path_length {
+                    if path_length(&image_path) > max {
+                        return Err(anyhow!(
+                            "Image path {:?} is {} characters long, over the max_path_length of {}",
+                            image_path,
+                            path_length(&image_path),
+                            max
+                        ));
+                    }
+                }
+                target.pending.push((image_path, image_data));
+
+                // Update HTML to reference the local file
+                let new_src = format!("{}{}", target.link_prefix, filename);
+
+                // Rewrite all occurrences, not just this tag's
                 modified_html = modified_html.replace(src, &new_src);
             }
         }
@@ -338,7 +1289,6 @@ fn extract_and_save_images(
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::fs;
     use tempfile::tempdir;
 
     #[test]
@@ -350,6 +1300,24 @@ mod tests {
         assert_eq!(config.subdir_format, "&folder");
         assert!(config.use_subdirs);
         assert!(!config.save_html);
+        assert!(config.outline_output.is_none());
+        assert!(!config.dedup_by_content);
+        assert!(!config.dedup_pointer_files);
+        assert!(!config.shorten_ids);
+        assert!(!config.file_per_folder);
+        assert!(config.osascript_path.is_none());
+        assert!(!config.detect_language);
+        assert!(!config.merge_accounts);
+        assert!(!config.trim_empty_headings);
+        assert!(config.gallery.is_none());
+        assert!(!config.detect_footnotes);
+        assert!(!config.lossy_utf8);
+        assert!(!config.incremental);
+        assert_eq!(config.watch_interval, None);
+        assert_eq!(config.emit_structure_diagram, None);
+        assert_eq!(config.max_path_length, None);
+        assert!(!config.prune_deleted);
+        assert!(!config.prune_dry_run);
     }
 
     #[test]
@@ -362,6 +1330,7 @@ mod tests {
             subdir_format: String::from("&folder"),
             use_subdirs: true,
             save_html: false,
+            ..ExportConfig::default()
         };
 
         let note = Note {
@@ -374,17 +1343,20 @@ mod tests {
             id: String::from("test-id"),
             created: String::from("2024-01-01"),
             modified: String::from("2024-01-01"),
+            flagged: false,
+            has_reminder: false,
         };
 
         let markdown = process_note(&note, &config)?;
-        assert!(markdown.contains("![](attachments/attachment-001.png)"));
+        let image_name = format!("attachment-{}-001.png", short_id("test-id"));
+        assert!(markdown.contains(&format!("![](attachments/{})", image_name)));
 
         // Check if image was saved
         let image_path = temp_dir
             .path()
             .join("Test Folder")
             .join("attachments")
-            .join("attachment-001.png");
+            .join(image_name);
         assert!(image_path.exists());
 
         Ok(())
@@ -400,6 +1372,7 @@ mod tests {
             subdir_format: String::from("&folder"),
             use_subdirs: true,
             save_html: false,
+            ..ExportConfig::default()
         };
 
         let note = Note {
@@ -412,6 +1385,8 @@ mod tests {
             id: String::from("test-id"),
             created: String::from("2024-01-01"),
             modified: String::from("2024-01-01"),
+            flagged: false,
+            has_reminder: false,
         };
 
         let markdown = process_note(&note, &config)?;
@@ -432,6 +1407,7 @@ mod tests {
             subdir_format: String::from("&folder"),
             use_subdirs: true,
             save_html: false,
+            ..ExportConfig::default()
         };
 
         let note = Note {
@@ -442,6 +1418,8 @@ mod tests {
             id: String::from("test-id"),
             created: String::from("2024-01-01"),
             modified: String::from("2024-01-01"),
+            flagged: false,
+            has_reminder: false,
         };
 
         let path = get_note_path(&note, &config)?;
@@ -456,4 +1434,726 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_render_format() {
+        let config = ExportConfig::default();
+        let note = Note {
+            title: String::from("Meeting/Notes"),
+            content: String::new(),
+            folder: String::from("Work"),
+            account: String::from("iCloud"),
+            id: String::from("x-coredata://1234/ICNote/p42"),
+            created: String::from("2024-01-01"),
+            modified: String::from("2024-01-01"),
+            flagged: false,
+            has_reminder: false,
+        };
+
+        assert_eq!(
+            render_format("&account/&folder", &note, &config),
+            "iCloud/Work"
+        );
+        assert_eq!(
+            render_format("&title & &id", &note, &config),
+            "Meeting-Notes & x-coredata:--1234-ICNote-p42"
+        );
+
+        let shortid = render_format("&shortid", &note, &config);
+        assert_eq!(shortid.len(), 8);
+        assert!(shortid
+            .chars()
+            .all(|c| c.is_ascii_digit() || c.is_ascii_lowercase()));
+        assert_eq!(shortid, short_id(&note.id));
+        assert_eq!(shortid, render_format("&shortid", &note, &config));
+        assert_ne!(shortid, short_id("x-coredata://1234/ICNote/p43"));
+
+        let config_short = ExportConfig {
+            shorten_ids: true,
+            ..config
+        };
+        assert_eq!(
+            render_format("&title-&id", &note, &config_short),
+            format!("Meeting-Notes-{}", shortid)
+        );
+    }
+
+    /// Records the program it was asked to run and prints `stdout`.
+    struct RecordingRunner {
+        programs: std::cell::RefCell<Vec<PathBuf>>,
+        stdout: Vec<u8>,
+    }
+
+    impl RecordingRunner {
+        fn new(stdout: &[u8]) -> Self {
+            Self {
+                programs: std::cell::RefCell::new(Vec::new()),
+                stdout: stdout.to_vec(),
+            }
+        }
+    }
+
+    impl CommandRunner for RecordingRunner {
+        fn run(&self, program: &Path, _args: &[&OsStr]) -> std::io::Result<CommandOutput> {
+            self.programs.borrow_mut().push(program.to_path_buf());
+            Ok(CommandOutput {
+                success: true,
+                stdout: self.stdout.clone(),
+                stderr: Vec::new(),
+            })
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_fetch_notes_uses_configured_osascript() -> Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp_dir = tempdir()?;
+        let osascript = temp_dir.path().join("osascript-wrapper");
+        fs::write(&osascript, "#!/bin/sh\n")?;
+        fs::set_permissions(&osascript, fs::Permissions::from_mode(0o755))?;
+
+        let config = ExportConfig {
+            osascript_path: Some(osascript.clone()),
+            ..ExportConfig::default()
+        };
+        let runner = RecordingRunner::new(b"[]");
+
+        let fetched = fetch_notes_with_runner(&config, &runner)?;
+        assert!(fetched.notes.is_empty());
+        assert_eq!(*runner.programs.borrow(), vec![osascript]);
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_fetch_notes_rejects_invalid_osascript() -> Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp_dir = tempdir()?;
+        let runner = RecordingRunner::new(b"[]");
+
+        let missing = ExportConfig {
+            osascript_path: Some(temp_dir.path().join("missing")),
+            ..ExportConfig::default()
+        };
+        let err = fetch_notes_with_runner(&missing, &runner).unwrap_err();
+        assert!(err.to_string().contains("not found"));
+
+        let not_executable = temp_dir.path().join("osascript");
+        fs::write(&not_executable, "")?;
+        fs::set_permissions(&not_executable, fs::Permissions::from_mode(0o644))?;
+        let config = ExportConfig {
+            osascript_path: Some(not_executable),
+            ..ExportConfig::default()
+        };
+        let err = fetch_notes_with_runner(&config, &runner).unwrap_err();
+        assert!(err.to_string().contains("not executable"));
+
+        assert!(runner.programs.borrow().is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_progress_reaches_total_bytes() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let config = ExportConfig {
+            output_dir: temp_dir.path().to_path_buf(),
+            ..ExportConfig::default()
+        };
+        let notes: Vec<Note> = ["<p>Short</p>", "<p>A somewhat longer note</p>"]
+            .iter()
+            .enumerate()
+            .map(|(i, content)| Note {
+                title: format!("Note {}", i),
+                content: String::from(*content),
+                folder: String::from("Test Folder"),
+                account: String::from("Test Account"),
+                id: format!("test-id-{}", i),
+                created: String::from("2024-01-01"),
+                modified: String::from("2024-01-01"),
+                flagged: false,
+                has_reminder: false,
+            })
+            .collect();
+        let total: u64 = notes.iter().map(|n| n.content.len() as u64).sum();
+
+        let mut updates = Vec::new();
+        write_notes_with_progress(&notes, &config, &mut |p| updates.push(*p))?;
+
+        assert_eq!(updates.len(), 2);
+        assert!(updates.iter().all(|p| p.bytes_total == total));
+        assert_eq!(updates[0].bytes_processed, notes[0].content.len() as u64);
+        let last = updates.last().unwrap();
+        assert_eq!(last.bytes_processed, total);
+        assert_eq!(last.notes_done, last.notes_total);
+        assert_eq!(last.fraction(), 1.0);
+
+        Ok(())
+    }
+
+    #[cfg(feature = "lang-detect")]
+    #[test]
+    fn test_frontmatter_detected_language() {
+        let config = ExportConfig {
+            detect_language: true,
+            ..ExportConfig::default()
+        };
+        let note = |content: &str| Note {
+            title: String::from("Test Note"),
+            content: String::from(content),
+            folder: String::from("Test Folder"),
+            account: String::from("Test Account"),
+            id: String::from("test-id"),
+            created: String::from("2024-01-01"),
+            modified: String::from("2024-01-01"),
+            flagged: false,
+            has_reminder: false,
+        };
+
+        let english = note("<p>The trip is booked and the hotel is close to the station.</p>");
+        assert!(frontmatter(&english, &config).contains("lang: en\n"));
+
+        let french = note("<p>Le voyage est réservé et l'hôtel est près de la gare.</p>");
+        assert!(frontmatter(&french, &config).contains("lang: fr\n"));
+
+        let short = note("<p>Groceries</p>");
+        assert!(!frontmatter(&short, &config).contains("lang:"));
+    }
+
+    #[test]
+    fn test_process_note_with_repeated_image() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let config = ExportConfig {
+            output_dir: temp_dir.path().to_path_buf(),
+            ..ExportConfig::default()
+        };
+        let image = "data:image/png;base64,iVBORw0KGgoAAAANSUhEUgAAAAEAAAABCAQAAAC1HAwCAAAAC0lEQVR42mNk+A8AAQUBAScY42YAAAAASUVORK5CYII=";
+
+        let note = Note {
+            title: String::from("Test Note"),
+            content: format!(
+                r#"<p>Before</p><img src="{0}"/><p>After</p><img src="{0}"/>"#,
+                image
+            ),
+            folder: String::from("Test Folder"),
+            account: String::from("Test Account"),
+            id: String::from("test-id"),
+            created: String::from("2024-01-01"),
+            modified: String::from("2024-01-01"),
+            flagged: false,
+            has_reminder: false,
+        };
+
+        let markdown = process_note(&note, &config)?;
+        let first = format!("attachment-{}-001.png", short_id("test-id"));
+        let second = format!("attachment-{}-002.png", short_id("test-id"));
+        assert_eq!(
+            markdown
+                .matches(&format!("![](attachments/{})", first))
+                .count(),
+            2
+        );
+        assert!(!markdown.contains("data:image"));
+
+        let attachments = temp_dir.path().join("Test Folder").join("attachments");
+        assert!(attachments.join(first).exists());
+        assert!(!attachments.join(second).exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_accounts_merges_same_named_folders() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let config = ExportConfig {
+            output_dir: temp_dir.path().to_path_buf(),
+            subdir_format: String::from("&account/&folder"),
+            merge_accounts: true,
+            ..ExportConfig::default()
+        };
+        let note = |title: &str, account: &str, id: &str| Note {
+            title: String::from(title),
+            content: format!("<p>{} from {}</p>", title, account),
+            folder: String::from("Work"),
+            account: String::from(account),
+            id: String::from(id),
+            created: String::from("2024-01-01"),
+            modified: String::from("2024-01-01"),
+            flagged: false,
+            has_reminder: false,
+        };
+        let notes = vec![
+            note("Roadmap", "iCloud", "icloud-1"),
+            note("Standup", "Exchange", "exchange-1"),
+            note("Roadmap", "Exchange", "exchange-2"),
+        ];
+
+        let report = write_notes(&notes, &config)?;
+
+        let work = temp_dir.path().join("Work");
+        let renamed = work.join(format!("Roadmap-{}.md", short_id("exchange-2")));
+        assert_eq!(
+            report.written,
+            vec![
+                work.join("Roadmap.md"),
+                work.join("Standup.md"),
+                renamed.clone()
+            ]
+        );
+        assert!(fs::read_to_string(work.join("Roadmap.md"))?.contains("Roadmap from iCloud"));
+        assert!(fs::read_to_string(renamed)?.contains("Roadmap from Exchange"));
+        assert!(!temp_dir.path().join("iCloud").exists());
+        assert!(!temp_dir.path().join("Exchange").exists());
+
+        let separate = ExportConfig {
+            merge_accounts: false,
+            ..config
+        };
+        let paths = plan_note_paths(&notes, &separate)?;
+        assert_eq!(paths[0], temp_dir.path().join("iCloud/Work/Roadmap.md"));
+        assert_eq!(paths[2], temp_dir.path().join("Exchange/Work/Roadmap.md"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_note_deserialization_flags() -> Result<()> {
+        let without: Note = serde_json::from_str(
+            r#"{"title":"A","content":"","folder":"F","account":"iCloud","id":"1","created":"2024-01-01","modified":"2024-01-01"}"#,
+        )?;
+        assert!(!without.flagged);
+        assert!(!without.has_reminder);
+
+        let with: Note = serde_json::from_str(
+            r#"{"title":"A","content":"","folder":"F","account":"iCloud","id":"1","created":"2024-01-01","modified":"2024-01-01","flagged":true,"has_reminder":true}"#,
+        )?;
+        assert!(with.flagged);
+        assert!(with.has_reminder);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_frontmatter_flagged_note() {
+        let config = ExportConfig::default();
+        let mut note = Note {
+            title: String::from("Test Note"),
+            content: String::new(),
+            folder: String::from("Test Folder"),
+            account: String::from("Test Account"),
+            id: String::from("test-id"),
+            created: String::from("2024-01-01"),
+            modified: String::from("2024-01-01"),
+            flagged: true,
+            has_reminder: false,
+        };
+
+        let frontmatter_flagged = frontmatter(&note, &config);
+        assert!(frontmatter_flagged.contains("modified: \"2024-01-01\"\nflagged: true\n"));
+        assert!(!frontmatter_flagged.contains("has_reminder"));
+
+        note.flagged = false;
+        assert!(!frontmatter(&note, &config).contains("flagged"));
+    }
+
+    #[test]
+    fn test_notes_in_one_folder_do_not_share_image_names() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let config = ExportConfig {
+            output_dir: temp_dir.path().to_path_buf(),
+            ..ExportConfig::default()
+        };
+        let note = |title: &str| Note {
+            title: String::from(title),
+            content: String::from(
+                r#"<img src="data:image/png;base64,iVBORw0KGgoAAAANSUhEUgAAAAEAAAABCAQAAAC1HAwCAAAAC0lEQVR42mNk+A8AAQUBAScY42YAAAAASUVORK5CYII="/>"#,
+            ),
+            folder: String::from("Test Folder"),
+            account: String::from("Test Account"),
+            id: format!("id-{}", title),
+            created: String::from("2024-01-01"),
+            modified: String::from("2024-01-01"),
+            flagged: false,
+            has_reminder: false,
+        };
+
+        let report = write_notes(&[note("First"), note("Second")], &config)?;
+
+        let folder = temp_dir.path().join("Test Folder");
+        let attachments = folder.join("attachments");
+        assert_eq!(
+            report.attachments,
+            vec![
+                ExportedAttachment {
+                    path: attachments.join(format!("attachment-{}-001.png", short_id("id-First"))),
+                    note_title: String::from("First"),
+                    note_path: folder.join("First.md"),
+                },
+                ExportedAttachment {
+                    path: attachments.join(format!("attachment-{}-001.png", short_id("id-Second"))),
+                    note_title: String::from("Second"),
+                    note_path: folder.join("Second.md"),
+                },
+            ]
+        );
+        assert!(
+            fs::read_to_string(folder.join("Second.md"))?.contains(&format!(
+                "![](attachments/attachment-{}-001.png)",
+                short_id("id-Second")
+            ))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_export_to_memory() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let output_dir = temp_dir.path().join("export");
+        let config = ExportConfig {
+            output_dir: output_dir.clone(),
+            ..ExportConfig::default()
+        };
+        let note = Note {
+            title: String::from("Test Note"),
+            content: String::from(
+                r#"<p>Test content</p><img src="data:image/png;base64,iVBORw0KGgoAAAANSUhEUgAAAAEAAAABCAQAAAC1HAwCAAAAC0lEQVR42mNk+A8AAQUBAScY42YAAAAASUVORK5CYII="/>"#,
+            ),
+            folder: String::from("Test Folder"),
+            account: String::from("Test Account"),
+            id: String::from("test-id"),
+            created: String::from("2024-01-01"),
+            modified: String::from("2024-01-01"),
+            flagged: false,
+            has_reminder: false,
+        };
+
+        let files = export_to_memory(&[note], &config)?;
+
+        assert_eq!(files.len(), 1);
+        let file = &files[0];
+        assert_eq!(
+            file.path,
+            output_dir.join("Test Folder").join("Test-Note.md")
+        );
+        assert!(file.markdown.starts_with("---\ntitle: \"Test Note\"\n"));
+        assert!(file.markdown.contains("Test content"));
+        let image_name = format!("attachment-{}-001.png", short_id("test-id"));
+        assert!(file
+            .markdown
+            .contains(&format!("![](attachments/{})", image_name)));
+
+        let image_path = output_dir
+            .join("Test Folder")
+            .join("attachments")
+            .join(image_name);
+        assert_eq!(file.attachments.len(), 1);
+        assert_eq!(file.attachments[0].0, image_path);
+        assert!(file.attachments[0].1.starts_with(b"\x89PNG"));
+
+        assert!(!output_dir.exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_fetch_notes_lossy_utf8() -> Result<()> {
+        let mut stdout = br#"[{"title":"Caf"#.to_vec();
+        stdout.push(0xE9); // Latin-1 "é", invalid on its own in UTF-8
+        stdout.extend_from_slice(
+            br#"","content":"<p>Menu</p>","folder":"Food","account":"iCloud","id":"1","created":"2024-01-01","modified":"2024-01-01"}]"#,
+        );
+        let runner = RecordingRunner::new(&stdout);
+
+        let strict = fetch_notes_with_runner(&ExportConfig::default(), &runner).unwrap_err();
+        assert!(strict.to_string().contains("UTF-8"));
+
+        let config = ExportConfig {
+            lossy_utf8: true,
+            ..ExportConfig::default()
+        };
+        let fetched = fetch_notes_with_runner(&config, &runner)?;
+        assert_eq!(fetched.notes.len(), 1);
+        assert_eq!(fetched.notes[0].title, "Caf\u{FFFD}");
+        assert_eq!(fetched.notes[0].content, "<p>Menu</p>");
+        assert_eq!(fetched.warnings.len(), 1);
+        assert!(fetched.warnings[0].contains("invalid UTF-8"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_incremental_export_rewrites_only_changed_notes() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let config = ExportConfig {
+            output_dir: temp_dir.path().to_path_buf(),
+            incremental: true,
+            ..ExportConfig::default()
+        };
+        let note = |title: &str, modified: &str| Note {
+            title: String::from(title),
+            content: format!("<p>{} at {}</p>", title, modified),
+            folder: String::from("Notes"),
+            account: String::from("iCloud"),
+            id: format!("id-{}", title),
+            created: String::from("2024-01-01"),
+            modified: String::from(modified),
+            flagged: false,
+            has_reminder: false,
+        };
+        let first = temp_dir.path().join("Notes").join("First.md");
+        let second = temp_dir.path().join("Notes").join("Second.md");
+
+        let report = write_notes(&[note("First", "1"), note("Second", "1")], &config)?;
+        assert_eq!(report.written, vec![first.clone(), second.clone()]);
+        assert!(report.unchanged.is_empty());
+
+        // A tick with only the second note edited rewrites only its file
+        let report = write_notes(&[note("First", "1"), note("Second", "2")], &config)?;
+        assert_eq!(report.written, vec![second.clone()]);
+        assert_eq!(report.unchanged, vec![first.clone()]);
+        assert!(fs::read_to_string(&second)?.contains("Second at 2"));
+
+        // A tick without edits writes nothing
+        let report = write_notes(&[note("First", "1"), note("Second", "2")], &config)?;
+        assert!(report.written.is_empty());
+        assert_eq!(report.unchanged, vec![first, second]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_reserved_windows_names() -> Result<()> {
+        assert_eq!(sanitize_filename("CON"), "CON_");
+        assert_eq!(sanitize_filename("lpt1"), "lpt1_");
+        assert_eq!(sanitize_filename("Console"), "Console");
+        assert_eq!(sanitize_filename("COM10"), "COM10");
+        assert_eq!(avoid_reserved_name("nul.md"), "nul_.md");
+        assert_eq!(avoid_reserved_name("Aux"), "Aux_");
+        assert_eq!(avoid_reserved_name("auxiliary.md"), "auxiliary.md");
+
+        let temp_dir = tempdir()?;
+        let config = ExportConfig {
+            output_dir: temp_dir.path().to_path_buf(),
+            ..ExportConfig::default()
+        };
+        let note = Note {
+            title: String::from("CON"),
+            content: String::from("<p>Console notes</p>"),
+            folder: String::from("PRN"),
+            account: String::from("iCloud"),
+            id: String::from("id-con"),
+            created: String::from("2024-01-01"),
+            modified: String::from("2024-01-01"),
+            flagged: false,
+            has_reminder: false,
+        };
+
+        let report = write_notes(&[note], &config)?;
+        assert_eq!(
+            report.written,
+            vec![temp_dir.path().join("PRN_").join("CON_.md")]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_path_length_shortens_long_paths() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let max = path_length(temp_dir.path()) + 100;
+        let config = ExportConfig {
+            output_dir: temp_dir.path().to_path_buf(),
+            subdir_format: String::from("&account/&folder"),
+            max_path_length: Some(max),
+            ..ExportConfig::default()
+        };
+        let long = "Very long name ".repeat(8);
+        let note = |title: &str, folder: &str, id: &str| {
+            Note {
+            title: format!("{}{}", long, title),
+            content: format!(
+                r#"<p>{}</p><img src="data:image/png;base64,{}"/>"#,
+                title,
+                "iVBORw0KGgoAAAANSUhEUgAAAAEAAAABCAQAAAC1HAwCAAAAC0lEQVR42mNk+A8AAQUBAScY42YAAAAASUVORK5CYII="
+            ),
+            folder: format!("{}{}", long, folder),
+            account: format!("{}account", long),
+            id: String::from(id),
+            created: String::from("2024-01-01"),
+            modified: String::from("2024-01-01"),
+            flagged: false,
+            has_reminder: false,
+        }
+        };
+        let notes = vec![
+            note("first", "A", "id-1"),
+            note("second", "A", "id-2"),
+            note("first", "B", "id-3"),
+        ];
+
+        let report = write_notes(&notes, &config)?;
+        assert_eq!(report.written.len(), 3);
+        let unique: HashSet<&PathBuf> = report.written.iter().collect();
+        assert_eq!(unique.len(), 3);
+        assert_ne!(report.written[0].parent(), report.written[2].parent());
+        for path in report
+            .written
+            .iter()
+            .chain(report.attachments.iter().map(|a| &a.path))
+        {
+            assert!(path_length(path) <= max, "{:?} is too long", path);
+            assert!(path.exists());
+        }
+
+        // Paths that fit are left alone
+        let short = note("", "", "id-4");
+        let short = Note {
+            title: String::from("Short"),
+            folder: String::from("Folder"),
+            account: String::from("iCloud"),
+            ..short
+        };
+        assert_eq!(
+            note_file_path(&short, &config)?,
+            temp_dir
+                .path()
+                .join("iCloud")
+                .join("Folder")
+                .join("Short.md")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_path_length_unreachable_is_an_error() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let output_dir = temp_dir.path().join("x".repeat(60));
+        let max = path_length(&output_dir);
+        let note = |content: &str| Note {
+            title: String::from("Note"),
+            content: String::from(content),
+            folder: String::from("Notes"),
+            account: String::from("iCloud"),
+            id: String::from("id-1"),
+            created: String::from("2024-01-01"),
+            modified: String::from("2024-01-01"),
+            flagged: false,
+            has_reminder: false,
+        };
+
+        // The output directory alone uses up the limit
+        let config = ExportConfig {
+            output_dir: output_dir.clone(),
+            max_path_length: Some(max),
+            ..ExportConfig::default()
+        };
+        let err = write_notes(&[note("<p>Text</p>")], &config).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains(&format!("max_path_length of {}", max)));
+        assert!(!output_dir.join("Notes").exists());
+
+        // An image extension longer than the room kept for images
+        let config = ExportConfig {
+            output_dir: output_dir.clone(),
+            max_path_length: Some(max + 100),
+            ..ExportConfig::default()
+        };
+        let image = format!(
+            r#"<img src="data:image/{};base64,iVBORw0KGgoAAAANSUhEUgAAAAEAAAABCAQAAAC1HAwCAAAAC0lEQVR42mNk+A8AAQUBAScY42YAAAAASUVORK5CYII="/>"#,
+            "x".repeat(100)
+        );
+        let err = write_notes(&[note(&image)], &config).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains(&format!("max_path_length of {}", max + 100)));
+        assert!(!output_dir.join("Notes").join("attachments").exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_incremental_export_keeps_html_of_unchanged_notes() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let config = ExportConfig {
+            output_dir: temp_dir.path().to_path_buf(),
+            incremental: true,
+            save_html: true,
+            ..ExportConfig::default()
+        };
+        let note = |modified: &str| Note {
+            title: String::from("Note"),
+            content: format!("<p>Edited {}</p>", modified),
+            folder: String::from("Notes"),
+            account: String::from("iCloud"),
+            id: String::from("id-1"),
+            created: String::from("2024-01-01"),
+            modified: String::from(modified),
+            flagged: false,
+            has_reminder: false,
+        };
+        let html = temp_dir.path().join("Notes").join("Note.html");
+
+        write_notes(&[note("2024-01-01")], &config)?;
+        fs::write(&html, "kept")?;
+
+        write_notes(&[note("2024-01-01")], &config)?;
+        assert_eq!(fs::read_to_string(&html)?, "kept");
+
+        write_notes(&[note("2024-02-01")], &config)?;
+        assert!(fs::read_to_string(&html)?.contains("Edited 2024-02-01"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_incremental_export_keeps_image_names_when_reordered() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let config = ExportConfig {
+            output_dir: temp_dir.path().to_path_buf(),
+            incremental: true,
+            ..ExportConfig::default()
+        };
+        // The image bytes are only decoded, so any base64 tells the images apart
+        let note = |title: &str, image: &str, modified: &str| Note {
+            title: String::from(title),
+            content: format!(
+                r#"<p>{}</p><img src="data:image/png;base64,{}"/>"#,
+                modified, image
+            ),
+            folder: String::from("Notes"),
+            account: String::from("iCloud"),
+            id: format!("id-{}", title),
+            created: String::from("2024-01-01"),
+            modified: String::from(modified),
+            flagged: false,
+            has_reminder: false,
+        };
+        let folder = temp_dir.path().join("Notes");
+        let image_of = |title: &str| -> Result<Vec<u8>> {
+            let markdown = fs::read_to_string(folder.join(format!("{}.md", title)))?;
+            let start = markdown.find("](").unwrap() + 2;
+            let end = start + markdown[start..].find(')').unwrap();
+            Ok(fs::read(folder.join(&markdown[start..end]))?)
+        };
+
+        write_notes(&[note("A", "QUFB", "1"), note("B", "QkJC", "1")], &config)?;
+        assert_eq!(image_of("A")?, b"AAA");
+        assert_eq!(image_of("B")?, b"BBB");
+
+        // Notes edited since the last export are listed first
+        let report = write_notes(&[note("B", "Q0ND", "2"), note("A", "QUFB", "1")], &config)?;
+        assert_eq!(report.written, vec![folder.join("B.md")]);
+        assert_eq!(report.unchanged, vec![folder.join("A.md")]);
+        assert_eq!(image_of("A")?, b"AAA");
+        assert_eq!(image_of("B")?, b"CCC");
+
+        Ok(())
+    }
 }
@@ -1,7 +1,9 @@
 use anyhow::Result;
-use apple_notes_exporter::{export_notes, ExportConfig};
+use apple_notes_exporter::{fetch_notes, write_notes_with_progress, ExportConfig};
 use clap::Parser;
+use std::io::Write;
 use std::path::PathBuf;
+use std::time::Duration;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
@@ -14,11 +16,11 @@ struct Cli {
     #[arg(short, long, default_value = "true")]
     use_attachments: bool,
 
-    /// Format for filenames
+    /// Format for filenames (&title, &folder, &account, &id, &shortid)
     #[arg(long, default_value = "&title")]
     filename_format: String,
 
-    /// Format for subdirectories
+    /// Format for subdirectories (&title, &folder, &account, &id, &shortid)
     #[arg(long, default_value = "&folder")]
     subdir_format: String,
 
@@ -29,6 +31,85 @@ struct Cli {
     /// Save HTML files alongside Markdown (for debugging)
     #[arg(long, default_value = "false")]
     save_html: bool,
+
+    /// Write an outline of the exported notes (OPML, or JSON for .json paths)
+    #[arg(long)]
+    outline: Option<PathBuf>,
+
+    /// Skip notes whose content duplicates a more recently modified note
+    #[arg(long, default_value = "false")]
+    dedup_by_content: bool,
+
+    /// Write a pointer file linking to the kept note for each skipped duplicate
+    #[arg(long, default_value = "false")]
+    dedup_pointer_files: bool,
+
+    /// Render &id as a short hash of the note id
+    #[arg(long, default_value = "false")]
+    shorten_ids: bool,
+
+    /// Write one Markdown file per folder containing all of its notes
+    #[arg(long, default_value = "false")]
+    file_per_folder: bool,
+
+    /// Path of the osascript binary (defaults to $NOTES2MD_OSASCRIPT, then PATH)
+    #[arg(long)]
+    osascript_path: Option<PathBuf>,
+
+    /// Ignore accounts in paths, merging same-named folders of all accounts
+    #[arg(long, default_value = "false")]
+    merge_accounts: bool,
+
+    /// Remove headings without text, such as a lone `##`
+    #[arg(long, default_value = "false")]
+    trim_empty_headings: bool,
+
+    /// Write an HTML page showing every extracted image
+    #[arg(long)]
+    gallery: Option<PathBuf>,
+
+    /// Convert superscript references and a trailing numbered list into footnotes
+    #[arg(long, default_value = "false")]
+    detect_footnotes: bool,
+
+    /// Replace invalid UTF-8 in the AppleScript output instead of failing
+    #[arg(long, default_value = "false")]
+    lossy_utf8: bool,
+
+    /// Only rewrite files of notes modified since they were last exported
+    #[arg(long, default_value = "false")]
+    incremental: bool,
+
+    /// Keep running, incrementally re-exporting every SECONDS (default 60)
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        num_args = 0..=1,
+        default_missing_value = "60",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    watch: Option<u64>,
+
+    /// Write a Mermaid diagram of accounts and folders (in a code block for .md paths)
+    #[arg(long)]
+    structure_diagram: Option<PathBuf>,
+
+    /// Shorten folder names and filenames so note paths stay within this many characters
+    #[arg(long)]
+    max_path_length: Option<usize>,
+
+    /// With --incremental or --watch, remove files of deleted, renamed or moved notes
+    #[arg(long, default_value = "false")]
+    prune_deleted: bool,
+
+    /// Only list the files --prune-deleted would remove
+    #[arg(long, default_value = "false")]
+    prune_dry_run: bool,
+
+    /// Detect each note's language and add it to the frontmatter as `lang`
+    #[cfg(feature = "lang-detect")]
+    #[arg(long, default_value = "false")]
+    detect_language: bool,
 }
 
 fn main() -> Result<()> {
@@ -44,10 +125,88 @@ fn main() -> Result<()> {
         subdir_format: cli.subdir_format,
         use_subdirs: cli.use_subdirs,
         save_html: cli.save_html,
+        outline_output: cli.outline,
+        dedup_by_content: cli.dedup_by_content,
+        dedup_pointer_files: cli.dedup_pointer_files,
+        shorten_ids: cli.shorten_ids,
+        file_per_folder: cli.file_per_folder,
+        osascript_path: cli.osascript_path,
+        merge_accounts: cli.merge_accounts,
+        trim_empty_headings: cli.trim_empty_headings,
+        gallery: cli.gallery,
+        detect_footnotes: cli.detect_footnotes,
+        lossy_utf8: cli.lossy_utf8,
+        // Watch mode would otherwise rewrite every note on every tick
+        incremental: cli.incremental || cli.watch.is_some(),
+        watch_interval: cli.watch.map(Duration::from_secs),
+        emit_structure_diagram: cli.structure_diagram,
+        max_path_length: cli.max_path_length,
+        prune_deleted: cli.prune_deleted,
+        prune_dry_run: cli.prune_dry_run,
+        #[cfg(feature = "lang-detect")]
+        detect_language: cli.detect_language,
+        #[cfg(not(feature = "lang-detect"))]
+        detect_language: false,
+    };
+
+    let Some(interval) = config.watch_interval else {
+        return export(&config);
     };
 
-    let notes = export_notes(&config)?;
-    println!("Successfully exported {} notes", notes.len());
+    println!("Watching for changes every {}s", interval.as_secs());
+    loop {
+        // Keep watching when a single poll fails, e.g. while Notes is busy syncing
+        if let Err(err) = export(&config) {
+            eprintln!("Export failed: {:#}", err);
+        }
+        std::thread::sleep(interval);
+    }
+}
+
+/// Exports all notes once, printing progress and a summary.
+fn export(config: &ExportConfig) -> Result<()> {
+    let fetched = fetch_notes(config)?;
+    for warning in &fetched.warnings {
+        println!("Warning: {}", warning);
+    }
+    let notes = fetched.notes;
+    let report = write_notes_with_progress(&notes, config, &mut |progress| {
+        print!(
+            "\rProcessed {}/{} notes ({:.0}%)",
+            progress.notes_done,
+            progress.notes_total,
+            progress.fraction() * 100.0
+        );
+        let _ = std::io::stdout().flush();
+    })?;
+    println!();
+    println!("Successfully exported {} notes", report.written.len());
+
+    for skipped in &report.skipped_duplicates {
+        println!(
+            "Skipped duplicate {:?} (same content as {:?})",
+            skipped.note.title, skipped.kept.title
+        );
+    }
+
+    if config.incremental {
+        for path in &report.written {
+            println!("Updated {:?}", path);
+        }
+        println!("{} notes unchanged", report.unchanged.len());
+    }
+
+    for warning in &report.warnings {
+        println!("Warning: {}", warning);
+    }
+
+    for path in &report.pruned {
+        if config.prune_dry_run {
+            println!("Would remove {:?}", path);
+        } else {
+            println!("Removed {:?}", path);
+        }
+    }
 
     Ok(())
 }
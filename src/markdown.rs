@@ -0,0 +1,320 @@
+//! Post-processing of the Markdown produced from a note's HTML.
+
+/// A line (or two-line setext heading) of Markdown, classified for post-processing.
+pub(crate) enum Block<'a> {
+    /// An empty or whitespace-only line
+    Blank,
+    /// A heading spanning `lines`; `empty` if it has no text
+    Heading {
+        level: usize,
+        empty: bool,
+        lines: Vec<&'a str>,
+    },
+    /// Anything else, including lines inside code fences
+    Content(&'a str),
+}
+
+/// Removes empty headings from Markdown.
+///
+/// A heading is removed if it has no text: a lone `##`, or an underline without a
+/// heading line, as html2md emits for an empty `<h1>`/`<h2>`. Headings with text are
+/// always kept, even when nothing follows them, so a note that is only a title keeps
+/// it.
+pub fn trim_empty_headings(markdown: &str) -> String {
+    let blocks = classify(markdown);
+
+    let mut lines: Vec<&str> = Vec::new();
+    for block in &blocks {
+        match block {
+            Block::Blank => {
+                // Removed headings leave their surrounding blank lines behind
+                if lines.last().is_some_and(|line| !line.trim().is_empty()) {
+                    lines.push("");
+                }
+            }
+            Block::Heading { empty: true, .. } => {}
+            Block::Heading { lines: heading, .. } => lines.extend(heading),
+            Block::Content(line) => lines.push(line),
+        }
+    }
+    while lines.last().is_some_and(|line| line.trim().is_empty()) {
+        lines.pop();
+    }
+
+    let mut trimmed = lines.join("\n");
+    if markdown.ends_with('\n') && !trimmed.is_empty() {
+        trimmed.push('\n');
+    }
+    trimmed
+}
+
+/// Converts emulated footnotes into Markdown footnote syntax.
+///
+/// Apple Notes has no footnotes, so they are usually written as a superscript number
+/// in the text (`<sup>1</sup>` or `¹`) and a numbered list at the end of the note
+/// (`1. Source`, `1) Source`, `<sup>1</sup> Source` or `¹ Source`). If every item of
+/// that trailing list is referenced from the text, the references become `[^1]` and
+/// the items `[^1]: Source`. Anything else is left alone, so a note that merely ends
+/// in a numbered list is not touched.
+pub fn convert_footnotes(markdown: &str) -> String {
+    let lines: Vec<&str> = markdown.lines().collect();
+
+    // Collect the trailing run of definition lines, allowing blank lines in between
+    let mut definitions: Vec<(usize, String, &str)> = Vec::new();
+    let mut body_end = lines.len();
+    for (index, line) in lines.iter().enumerate().rev() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match footnote_definition(line) {
+            Some((number, text)) => {
+                definitions.push((index, number, text));
+                body_end = index;
+            }
+            None => break,
+        }
+    }
+    if definitions.is_empty() {
+        return markdown.to_string();
+    }
+
+    let body = lines[..body_end].join("\n");
+    let all_referenced = definitions.iter().all(|(_, number, _)| {
+        body.contains(&format!("<sup>{}</sup>", number)) || body.contains(&to_superscript(number))
+    });
+    if !all_referenced {
+        return markdown.to_string();
+    }
+
+    let mut body = body;
+    // Replace longer numbers first so `1` does not match inside `12`
+    let mut numbers: Vec<&String> = definitions.iter().map(|(_, number, _)| number).collect();
+    numbers.sort_by_key(|number| std::cmp::Reverse(number.len()));
+    for number in numbers {
+        let reference = format!("[^{}]", number);
+        body = body
+            .replace(&format!("<sup>{}</sup>", number), &reference)
+            .replace(&to_superscript(number), &reference);
+    }
+
+    let mut converted = body;
+    for (index, line) in lines.iter().enumerate().skip(body_end) {
+        converted.push('\n');
+        match definitions.iter().find(|(i, _, _)| *i == index) {
+            Some((_, number, text)) => converted.push_str(&format!("[^{}]: {}", number, text)),
+            None => converted.push_str(line),
+        }
+    }
+    if markdown.ends_with('\n') {
+        converted.push('\n');
+    }
+    converted
+}
+
+/// Parses an emulated footnote definition into its number and text.
+fn footnote_definition(line: &str) -> Option<(String, &str)> {
+    let line = line.trim();
+
+    if let Some(rest) = line.strip_prefix("<sup>") {
+        let (number, text) = rest.split_once("</sup>")?;
+        return is_number(number).then(|| (number.to_string(), text.trim()));
+    }
+
+    let superscript: String = line
+        .chars()
+        .take_while(|c| from_superscript(*c).is_some())
+        .collect();
+    if !superscript.is_empty() {
+        let number = superscript.chars().filter_map(from_superscript).collect();
+        return Some((number, line[superscript.len()..].trim()));
+    }
+
+    let digits = line.chars().take_while(|c| c.is_ascii_digit()).count();
+    let rest = &line[digits..];
+    let text = rest
+        .strip_prefix(". ")
+        .or_else(|| rest.strip_prefix(") "))?;
+    (digits > 0).then(|| (line[..digits].to_string(), text.trim()))
+}
+
+fn is_number(text: &str) -> bool {
+    !text.is_empty() && text.chars().all(|c| c.is_ascii_digit())
+}
+
+const SUPERSCRIPT_DIGITS: [char; 10] = ['⁰', '¹', '²', '³', '⁴', '⁵', '⁶', '⁷', '⁸', '⁹'];
+
+fn to_superscript(number: &str) -> String {
+    number
+        .chars()
+        .filter_map(|c| c.to_digit(10))
+        .map(|d| SUPERSCRIPT_DIGITS[d as usize])
+        .collect()
+}
+
+fn from_superscript(c: char) -> Option<char> {
+    SUPERSCRIPT_DIGITS
+        .iter()
+        .position(|d| *d == c)
+        .and_then(|d| char::from_digit(d as u32, 10))
+}
+
+/// Splits Markdown into blocks, recognizing ATX and setext headings outside code
+/// fences.
+pub(crate) fn classify(markdown: &str) -> Vec<Block<'_>> {
+    let lines: Vec<&str> = markdown.lines().collect();
+    let mut blocks = Vec::with_capacity(lines.len());
+    let mut in_fence = false;
+    let mut i = 0;
+
+    while i < lines.len() {
+        let line = lines[i];
+        let trimmed = line.trim();
+
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            in_fence = !in_fence;
+            blocks.push(Block::Content(line));
+        } else if in_fence {
+            blocks.push(Block::Content(line));
+        } else if trimmed.is_empty() {
+            blocks.push(Block::Blank);
+        } else if let Some((level, empty)) = atx_heading(trimmed) {
+            blocks.push(Block::Heading {
+                level,
+                empty,
+                lines: vec![line],
+            });
+        } else if let Some(level) = lines.get(i + 1).and_then(|next| setext_underline(next)) {
+            blocks.push(Block::Heading {
+                level,
+                empty: false,
+                lines: vec![line, lines[i + 1]],
+            });
+            i += 1;
+        } else if is_lone_underline(trimmed) {
+            blocks.push(Block::Heading {
+                level: setext_underline(trimmed).unwrap_or(2),
+                empty: true,
+                lines: vec![line],
+            });
+        } else {
+            blocks.push(Block::Content(line));
+        }
+
+        i += 1;
+    }
+
+    blocks
+}
+
+/// Parses an ATX heading (`## Title`, `### Title ###`) into its level and emptiness.
+fn atx_heading(line: &str) -> Option<(usize, bool)> {
+    let level = line.chars().take_while(|c| *c == '#').count();
+    if level == 0 || level > 6 {
+        return None;
+    }
+
+    let rest = &line[level..];
+    if !rest.is_empty() && !rest.starts_with(char::is_whitespace) {
+        return None;
+    }
+
+    let text = rest.trim().trim_end_matches('#').trim();
+    Some((level, text.is_empty()))
+}
+
+/// Returns the heading level of a setext underline (`===` or `---`).
+fn setext_underline(line: &str) -> Option<usize> {
+    let line = line.trim();
+    if line.is_empty() {
+        None
+    } else if line.chars().all(|c| c == '=') {
+        Some(1)
+    } else if line.chars().all(|c| c == '-') {
+        Some(2)
+    } else {
+        None
+    }
+}
+
+/// Whether a line is a setext underline with no heading text above it.
+///
+/// html2md draws a `<hr>` as `---`, so only longer runs of `-` count as headings.
+fn is_lone_underline(line: &str) -> bool {
+    match setext_underline(line) {
+        Some(1) => true,
+        Some(_) => line.len() > 3,
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trim_trailing_empty_heading() {
+        let markdown = "# Title\n\nSome text\n\n## Section\n\nMore text\n\n##\n";
+        assert_eq!(
+            trim_empty_headings(markdown),
+            "# Title\n\nSome text\n\n## Section\n\nMore text\n"
+        );
+    }
+
+    #[test]
+    fn test_trim_keeps_headings_without_content() {
+        let markdown = "Intro\n\n## Empty\n\n## Kept\n\nText\n\n### Sub\n\n####  ####\n\n## Last";
+        assert_eq!(
+            trim_empty_headings(markdown),
+            "Intro\n\n## Empty\n\n## Kept\n\nText\n\n### Sub\n\n## Last"
+        );
+    }
+
+    #[test]
+    fn test_trim_keeps_title_only_note() {
+        let markdown = html2md::parse_html("<h1>Shopping</h1>");
+        assert!(markdown.contains("Shopping"));
+        assert_eq!(trim_empty_headings(&markdown), markdown.trim_end());
+    }
+
+    #[test]
+    fn test_trim_html2md_setext_headings() {
+        let markdown = "A\n==========\n\nx\n\nB\n----------\n\n----------";
+        assert_eq!(
+            trim_empty_headings(markdown),
+            "A\n==========\n\nx\n\nB\n----------"
+        );
+
+        let with_rule = "a\n\n---\n\nb";
+        assert_eq!(trim_empty_headings(with_rule), with_rule);
+    }
+
+    #[test]
+    fn test_trim_ignores_code_fences() {
+        let markdown = "## Script\n\n```\n#\n```\n";
+        assert_eq!(trim_empty_headings(markdown), markdown);
+    }
+
+    #[test]
+    fn test_convert_footnotes() {
+        let markdown = "Paris is big<sup>1</sup>.\n\n1. Source: atlas";
+        assert_eq!(
+            convert_footnotes(markdown),
+            "Paris is big[^1].\n\n[^1]: Source: atlas"
+        );
+
+        let superscript = "See here¹ and there².\n\n¹ First\n² Second\n";
+        assert_eq!(
+            convert_footnotes(superscript),
+            "See here[^1] and there[^2].\n\n[^1]: First\n[^2]: Second\n"
+        );
+    }
+
+    #[test]
+    fn test_convert_footnotes_leaves_plain_lists() {
+        let markdown = "Steps:\n\n1. Open\n2. Close";
+        assert_eq!(convert_footnotes(markdown), markdown);
+
+        let partly = "Only one<sup>1</sup>\n\n1. First\n2. Second";
+        assert_eq!(convert_footnotes(partly), partly);
+    }
+}
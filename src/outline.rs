@@ -0,0 +1,288 @@
+//! Outline export of the account → folder → note hierarchy.
+//!
+//! The outline is written as OPML by default, or as JSON when the output path has a
+//! `.json` extension. Every note entry links to its exported Markdown file, relative
+//! to the directory of the outline file.
+
+use crate::{plan_note_paths, relative_link, ExportConfig, Note};
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+
+/// An account and the folders it contains.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct OutlineAccount {
+    /// The account name (e.g., "iCloud")
+    pub name: String,
+    /// The folders of the account
+    pub folders: Vec<OutlineFolder>,
+}
+
+/// A folder and the notes it contains.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct OutlineFolder {
+    /// The folder name
+    pub name: String,
+    /// The notes in the folder
+    pub notes: Vec<OutlineNote>,
+}
+
+/// A single note entry of the outline.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct OutlineNote {
+    /// The title of the note
+    pub title: String,
+    /// Path of the exported file, relative to the directory the outline links from
+    pub link: String,
+}
+
+/// Builds the account → folder → note hierarchy of the given notes.
+///
+/// Accounts and folders keep the order in which they first appear in `notes`. Note
+/// links are relative to `link_dir`.
+///
+/// # Errors
+/// * If the output path of a note cannot be determined
+pub fn build_outline(
+    notes: &[Note],
+    config: &ExportConfig,
+    link_dir: &Path,
+) -> Result<Vec<OutlineAccount>> {
+    let mut accounts: Vec<OutlineAccount> = Vec::new();
+
+    for (note, file_path) in notes.iter().zip(plan_note_paths(notes, config)?) {
+        let link = relative_link(link_dir, &file_path);
+
+        let account_index = match accounts.iter().position(|a| a.name == note.account) {
+            Some(index) => index,
+            None => {
+                accounts.push(OutlineAccount {
+                    name: note.account.clone(),
+                    folders: Vec::new(),
+                });
+                accounts.len() - 1
+            }
+        };
+        let folders = &mut accounts[account_index].folders;
+
+        let folder_index = match folders.iter().position(|f| f.name == note.folder) {
+            Some(index) => index,
+            None => {
+                folders.push(OutlineFolder {
+                    name: note.folder.clone(),
+                    notes: Vec::new(),
+                });
+                folders.len() - 1
+            }
+        };
+
+        folders[folder_index].notes.push(OutlineNote {
+            title: note.title.clone(),
+            link,
+        });
+    }
+
+    Ok(accounts)
+}
+
+/// Renders the outline as an OPML 2.0 document.
+pub fn render_opml(accounts: &[OutlineAccount]) -> String {
+    let mut opml = String::new();
+    opml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    opml.push_str("<opml version=\"2.0\">\n");
+    opml.push_str("  <head>\n    <title>Apple Notes</title>\n  </head>\n");
+    opml.push_str("  <body>\n");
+
+    for account in accounts {
+        opml.push_str(&format!(
+            "    <outline text=\"{}\">\n",
+            escape_xml(&account.name)
+        ));
+        for folder in &account.folders {
+            opml.push_str(&format!(
+                "      <outline text=\"{}\">\n",
+                escape_xml(&folder.name)
+            ));
+            for note in &folder.notes {
+                opml.push_str(&format!(
+                    "        <outline text=\"{}\" type=\"link\" url=\"{}\"/>\n",
+                    escape_xml(&note.title),
+                    escape_xml(&note.link)
+                ));
+            }
+            opml.push_str("      </outline>\n");
+        }
+        opml.push_str("    </outline>\n");
+    }
+
+    opml.push_str("  </body>\n");
+    opml.push_str("</opml>\n");
+    opml
+}
+
+/// Writes the outline of the given notes to `path`.
+///
+/// # Errors
+/// * If the outline cannot be built
+/// * If the outline file cannot be written
+pub fn write_outline(notes: &[Note], config: &ExportConfig, path: &Path) -> Result<()> {
+    let outline_dir = path.parent().unwrap_or(Path::new(""));
+    let accounts = build_outline(notes, config, outline_dir)?;
+
+    let content = if path.extension().is_some_and(|ext| ext == "json") {
+        serde_json::to_string_pretty(&accounts).context("Failed to serialize outline")?
+    } else {
+        render_opml(&accounts)
+    };
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory: {:?}", parent))?;
+    }
+    fs::write(path, content.as_bytes())
+        .with_context(|| format!("Failed to write outline file: {:?}", path))?;
+
+    Ok(())
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::absolute_path;
+    use tempfile::tempdir;
+
+    fn note(title: &str, folder: &str) -> Note {
+        Note {
+            title: String::from(title),
+            content: String::from("<p>Content</p>"),
+            folder: String::from(folder),
+            account: String::from("iCloud"),
+            id: format!("id-{}", title),
+            created: String::from("2024-01-01"),
+            modified: String::from("2024-01-01"),
+            flagged: false,
+            has_reminder: false,
+        }
+    }
+
+    #[test]
+    fn test_outline_nests_folders_and_notes() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let config = ExportConfig {
+            output_dir: temp_dir.path().to_path_buf(),
+            ..ExportConfig::default()
+        };
+        let notes = vec![
+            note("Groceries", "Personal"),
+            note("Roadmap", "Work"),
+            note("Trips", "Personal"),
+        ];
+
+        let accounts = build_outline(&notes, &config, temp_dir.path())?;
+        assert_eq!(accounts.len(), 1);
+        assert_eq!(accounts[0].name, "iCloud");
+
+        let folders = &accounts[0].folders;
+        assert_eq!(folders.len(), 2);
+        assert_eq!(folders[0].name, "Personal");
+        assert_eq!(
+            folders[0].notes,
+            vec![
+                OutlineNote {
+                    title: String::from("Groceries"),
+                    link: String::from("Personal/Groceries.md"),
+                },
+                OutlineNote {
+                    title: String::from("Trips"),
+                    link: String::from("Personal/Trips.md"),
+                },
+            ]
+        );
+        assert_eq!(folders[1].name, "Work");
+        assert_eq!(
+            folders[1].notes,
+            vec![OutlineNote {
+                title: String::from("Roadmap"),
+                link: String::from("Work/Roadmap.md"),
+            }]
+        );
+
+        let opml = render_opml(&accounts);
+        let personal = opml.find("<outline text=\"Personal\">").unwrap();
+        let work = opml.find("<outline text=\"Work\">").unwrap();
+        let groceries = opml
+            .find("<outline text=\"Groceries\" type=\"link\" url=\"Personal/Groceries.md\"/>")
+            .unwrap();
+        let roadmap = opml
+            .find("<outline text=\"Roadmap\" type=\"link\" url=\"Work/Roadmap.md\"/>")
+            .unwrap();
+        assert!(personal < groceries && groceries < work && work < roadmap);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_outline_json() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let outline_path = temp_dir.path().join("outline.json");
+        let output_dir = temp_dir.path().join("my-notes");
+        let config = ExportConfig {
+            output_dir: output_dir.clone(),
+            outline_output: Some(outline_path.clone()),
+            ..ExportConfig::default()
+        };
+
+        crate::write_notes(&[note("Roadmap", "Work")], &config)?;
+
+        let json: serde_json::Value = serde_json::from_str(&fs::read_to_string(&outline_path)?)?;
+        assert_eq!(json[0]["folders"][0]["name"], "Work");
+        assert_eq!(
+            json[0]["folders"][0]["notes"][0]["link"],
+            "my-notes/Work/Roadmap.md"
+        );
+        assert!(output_dir.join("Work").join("Roadmap.md").exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_outline_links_mix_absolute_and_relative_paths() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let cwd = std::env::current_dir()?;
+        let notes = [note("Roadmap", "Work")];
+        let link = |output_dir: &Path, link_dir: &Path| -> Result<String> {
+            let config = ExportConfig {
+                output_dir: output_dir.to_path_buf(),
+                ..ExportConfig::default()
+            };
+            let accounts = build_outline(&notes, &config, link_dir)?;
+            Ok(accounts[0].folders[0].notes[0].link.clone())
+        };
+
+        // An absolute export, linked from an outline in the current directory
+        let link_to_absolute = link(temp_dir.path(), Path::new(""))?;
+        assert!(!link_to_absolute.starts_with('/'));
+        assert_eq!(
+            absolute_path(&cwd.join(&link_to_absolute)),
+            temp_dir.path().join("Work").join("Roadmap.md")
+        );
+
+        // A relative export, linked from an outline at an absolute path
+        let link_to_relative = link(Path::new("my-notes"), temp_dir.path())?;
+        assert_eq!(
+            absolute_path(&temp_dir.path().join(&link_to_relative)),
+            cwd.join("my-notes").join("Work").join("Roadmap.md")
+        );
+
+        Ok(())
+    }
+}
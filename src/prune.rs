@@ -0,0 +1,415 @@
+//! Removal of exported files that the latest export no longer produces.
+//!
+//! These are the files of notes deleted from Apple Notes, and the old files of notes
+//! that were renamed or moved. Exported Markdown files are recognized by their
+//! frontmatter: a file is only considered if it has the `id`, `folder` and `account`
+//! fields the exporter writes, so other Markdown files in the output directory are
+//! never touched. The images of a removed file go with it, unless the note still has
+//! a file in the same directory.
+//!
+//! Symbolic links and hidden directories are not followed. When no exported note
+//! would remain, e.g. because Notes returned nothing while still syncing, pruning is
+//! skipped with a warning instead of emptying the export.
+
+use crate::{frontmatter_value, short_id, ExportConfig, ExportReport, Note};
+use anyhow::{Context, Result};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+/// Removes exported note files below the output directory that are not among the
+/// files `report` lists as written, unchanged or pointers, recording them in
+/// `report.pruned`.
+///
+/// `report` must describe a complete export of the latest fetch, so that every file
+/// it does not list belongs to a deleted, renamed or moved note. With
+/// `config.prune_dry_run` set, the files are only reported.
+///
+/// Nothing is pruned, and a warning is added to `report` instead, when `notes` is
+/// empty or contains none of the `previous` note ids, which are the ones
+/// [`exported_note_ids`] returned before the export.
+///
+/// # Errors
+/// * If the output directory cannot be read
+/// * If a file cannot be removed
+pub fn prune_deleted(
+    config: &ExportConfig,
+    notes: &[Note],
+    previous: &HashSet<String>,
+    report: &mut ExportReport,
+) -> Result<()> {
+    let exported: HashSet<&PathBuf> = report
+        .written
+        .iter()
+        .chain(&report.unchanged)
+        .chain(&report.pointers)
+        .collect();
+
+    let markdown_files = collect_markdown_files(&config.output_dir)?;
+
+    // Notes that still have a file in a directory keep their images there
+    let mut kept: HashSet<(PathBuf, String)> = HashSet::new();
+    let mut stale = Vec::new();
+    for path in markdown_files {
+        let content = fs::read_to_string(&path).unwrap_or_default();
+        let Some(id) = exported_note_id(&content) else {
+            continue;
+        };
+        let dir = path.parent().unwrap_or(Path::new("")).to_path_buf();
+        if exported.contains(&path) {
+            kept.insert((dir, id));
+        } else {
+            stale.push((path, dir, id));
+        }
+    }
+
+    if !stale.is_empty() {
+        let any_previous_kept = notes
+            .iter()
+            .chain(
+                report
+                    .skipped_duplicates
+                    .iter()
+                    .map(|skipped| &skipped.note),
+            )
+            .any(|note| previous.contains(&note.id));
+        let reason = if notes.is_empty() {
+            Some("the latest fetch returned no notes")
+        } else if !previous.is_empty() && !any_previous_kept {
+            Some("it would remove every previously exported note")
+        } else {
+            None
+        };
+        if let Some(reason) = reason {
+            report.warnings.push(format!(
+                "Not pruning {} exported files because {}",
+                stale.len(),
+                reason
+            ));
+            return Ok(());
+        }
+    }
+
+    let mut pruned = Vec::new();
+    for (path, dir, id) in stale {
+        pruned.push(path.clone());
+
+        let html = path.with_extension("html");
+        if html.is_file() {
+            pruned.push(html);
+        }
+
+        if !kept.contains(&(dir.clone(), id.clone())) {
+            pruned.extend(note_images(&dir, &id)?);
+        }
+    }
+
+    for file in pruned {
+        if !config.prune_dry_run {
+            fs::remove_file(&file).with_context(|| format!("Failed to remove file: {:?}", file))?;
+        }
+        report.pruned.push(file);
+    }
+
+    Ok(())
+}
+
+/// Returns the ids of all notes with an exported file below the output directory.
+///
+/// # Errors
+/// * If the output directory cannot be read
+pub fn exported_note_ids(config: &ExportConfig) -> Result<HashSet<String>> {
+    Ok(collect_markdown_files(&config.output_dir)?
+        .iter()
+        .filter_map(|path| exported_note_id(&fs::read_to_string(path).unwrap_or_default()))
+        .collect())
+}
+
+/// Returns the note id of an exported note file, or `None` for other Markdown files.
+fn exported_note_id(content: &str) -> Option<String> {
+    frontmatter_value(content, "folder")?;
+    frontmatter_value(content, "account")?;
+    frontmatter_value(content, "id")
+}
+
+/// Collects all `.md` files below `dir`, sorted for a stable report.
+///
+/// Symbolic links are neither followed nor returned, and hidden directories such as
+/// `.git` are skipped.
+fn collect_markdown_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let walker = WalkDir::new(dir)
+        .follow_links(false)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|entry| {
+            entry.depth() == 0
+                || !entry.file_type().is_dir()
+                || !entry.file_name().to_string_lossy().starts_with('.')
+        });
+
+    let mut files = Vec::new();
+    for entry in walker {
+        let entry = entry.with_context(|| format!("Failed to read directory: {:?}", dir))?;
+        if entry.file_type().is_file() && entry.path().extension().is_some_and(|ext| ext == "md") {
+            files.push(entry.into_path());
+        }
+    }
+
+    Ok(files)
+}
+
+/// Lists the extracted images of the note with `id` below a note file's `dir`.
+fn note_images(dir: &Path, id: &str) -> Result<Vec<PathBuf>> {
+    let prefix = format!("attachment-{}-", short_id(id));
+    let mut images = Vec::new();
+    for images_dir in [dir.join("attachments"), dir.to_path_buf()] {
+        // A symlinked attachments directory may point outside the export
+        let is_dir = fs::symlink_metadata(&images_dir).is_ok_and(|meta| meta.is_dir());
+        if !is_dir {
+            continue;
+        }
+        for entry in fs::read_dir(&images_dir)
+            .with_context(|| format!("Failed to read directory: {:?}", images_dir))?
+        {
+            let entry = entry?;
+            let path = entry.path();
+            let is_image = path
+                .file_name()
+                .is_some_and(|name| name.to_string_lossy().starts_with(&prefix));
+            if is_image && entry.file_type()?.is_file() {
+                images.push(path);
+            }
+        }
+    }
+    images.sort();
+    Ok(images)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{write_notes, Note};
+    use tempfile::tempdir;
+
+    const PIXEL: &str = "iVBORw0KGgoAAAANSUhEUgAAAAEAAAABCAQAAAC1HAwCAAAAC0lEQVR42mNk+A8AAQUBAScY42YAAAAASUVORK5CYII=";
+
+    fn note(title: &str) -> Note {
+        Note {
+            title: String::from(title),
+            content: format!(
+                r#"<p>{}</p><img src="data:image/png;base64,{}"/>"#,
+                title, PIXEL
+            ),
+            folder: String::from("Notes"),
+            account: String::from("iCloud"),
+            id: format!("id-{}", title),
+            created: String::from("2024-01-01"),
+            modified: String::from("2024-01-01"),
+            flagged: false,
+            has_reminder: false,
+        }
+    }
+
+    #[test]
+    fn test_prune_deleted_notes() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let notes_dir = temp_dir.path().join("Notes");
+        let config = ExportConfig {
+            output_dir: temp_dir.path().to_path_buf(),
+            incremental: true,
+            prune_deleted: true,
+            ..ExportConfig::default()
+        };
+        write_notes(&[note("Kept"), note("Deleted")], &config)?;
+        fs::write(
+            notes_dir.join("README.md"),
+            "---\nid: \"mine\"\n---\n\nNot a note",
+        )?;
+
+        let deleted = vec![
+            notes_dir.join("Deleted.md"),
+            notes_dir.join("attachments").join(format!(
+                "attachment-{}-001.png",
+                crate::short_id("id-Deleted")
+            )),
+        ];
+
+        // A dry run only reports what would be removed
+        let dry_run = ExportConfig {
+            prune_dry_run: true,
+            ..config.clone()
+        };
+        let report = write_notes(&[note("Kept")], &dry_run)?;
+        assert_eq!(report.pruned, deleted);
+        assert!(deleted.iter().all(|path| path.exists()));
+
+        let report = write_notes(&[note("Kept")], &config)?;
+        assert_eq!(report.pruned, deleted);
+        assert!(deleted.iter().all(|path| !path.exists()));
+        assert!(notes_dir.join("Kept.md").exists());
+        assert!(notes_dir
+            .join("attachments")
+            .join(format!("attachment-{}-001.png", crate::short_id("id-Kept")))
+            .exists());
+        assert!(notes_dir.join("README.md").exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_prune_then_add_keeps_other_images() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let notes_dir = temp_dir.path().join("Notes");
+        let config = ExportConfig {
+            output_dir: temp_dir.path().to_path_buf(),
+            incremental: true,
+            prune_deleted: true,
+            ..ExportConfig::default()
+        };
+        let image = |title: &str| {
+            notes_dir.join("attachments").join(format!(
+                "attachment-{}-001.png",
+                short_id(&format!("id-{}", title))
+            ))
+        };
+
+        write_notes(&[note("A"), note("B"), note("C")], &config)?;
+        let report = write_notes(&[note("A"), note("C")], &config)?;
+        assert_eq!(report.pruned, vec![notes_dir.join("B.md"), image("B")]);
+
+        let report = write_notes(&[note("A"), note("C"), note("D")], &config)?;
+        assert!(report.pruned.is_empty());
+        assert_eq!(report.written, vec![notes_dir.join("D.md")]);
+        for title in ["A", "C", "D"] {
+            let markdown = fs::read_to_string(notes_dir.join(format!("{}.md", title)))?;
+            let link = image(title);
+            let name = link.file_name().unwrap().to_string_lossy();
+            assert!(markdown.contains(&format!("(attachments/{})", name)));
+            assert!(link.exists());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_prune_renamed_and_moved_notes() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let config = ExportConfig {
+            output_dir: temp_dir.path().to_path_buf(),
+            incremental: true,
+            prune_deleted: true,
+            ..ExportConfig::default()
+        };
+        let image_name = format!("attachment-{}-001.png", short_id("id-Old"));
+        let old = note("Old");
+        let renamed = Note {
+            title: String::from("New"),
+            modified: String::from("2024-02-01"),
+            ..old.clone()
+        };
+        let moved = Note {
+            folder: String::from("Archive"),
+            modified: String::from("2024-03-01"),
+            ..renamed.clone()
+        };
+        let notes_dir = temp_dir.path().join("Notes");
+        let archive_dir = temp_dir.path().join("Archive");
+
+        write_notes(&[old], &config)?;
+
+        // A renamed note keeps its images, which are named after its id
+        let report = write_notes(&[renamed], &config)?;
+        assert_eq!(report.written, vec![notes_dir.join("New.md")]);
+        assert_eq!(report.pruned, vec![notes_dir.join("Old.md")]);
+        assert!(notes_dir.join("attachments").join(&image_name).exists());
+
+        // A moved note leaves nothing behind in its old folder
+        let report = write_notes(&[moved], &config)?;
+        assert_eq!(report.written, vec![archive_dir.join("New.md")]);
+        assert_eq!(
+            report.pruned,
+            vec![
+                notes_dir.join("New.md"),
+                notes_dir.join("attachments").join(&image_name),
+            ]
+        );
+        assert!(archive_dir.join("attachments").join(&image_name).exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_prune_skipped_when_no_note_would_remain() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let notes_dir = temp_dir.path().join("Notes");
+        let config = ExportConfig {
+            output_dir: temp_dir.path().to_path_buf(),
+            incremental: true,
+            prune_deleted: true,
+            ..ExportConfig::default()
+        };
+        write_notes(&[note("A"), note("B")], &config)?;
+
+        // An empty fetch, e.g. while Notes is still syncing
+        let report = write_notes(&[], &config)?;
+        assert!(report.pruned.is_empty());
+        assert_eq!(report.warnings.len(), 1);
+        assert!(report.warnings[0].contains("returned no notes"));
+        assert!(notes_dir.join("A.md").exists());
+
+        // Every exported note would go, e.g. after the wrong account was fetched
+        let other = Note {
+            folder: String::from("Other"),
+            ..note("C")
+        };
+        let report = write_notes(&[other], &config)?;
+        assert!(report.pruned.is_empty());
+        assert_eq!(report.warnings.len(), 1);
+        assert!(report.warnings[0].contains("every previously exported note"));
+
+        assert!(notes_dir.join("A.md").exists());
+        assert!(notes_dir.join("B.md").exists());
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_prune_does_not_follow_symlinks() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let outside = tempdir()?;
+        let config = ExportConfig {
+            output_dir: temp_dir.path().to_path_buf(),
+            incremental: true,
+            prune_deleted: true,
+            ..ExportConfig::default()
+        };
+
+        // Another export reachable through a symlink, and a symlink loop
+        let linked = ExportConfig {
+            output_dir: outside.path().to_path_buf(),
+            ..config.clone()
+        };
+        write_notes(&[note("Elsewhere")], &linked)?;
+        std::os::unix::fs::symlink(outside.path(), temp_dir.path().join("linked"))?;
+        std::os::unix::fs::symlink(temp_dir.path(), temp_dir.path().join("loop"))?;
+
+        write_notes(&[note("A"), note("B")], &config)?;
+        let report = write_notes(&[note("A")], &config)?;
+
+        let notes_dir = temp_dir.path().join("Notes");
+        assert_eq!(
+            report.pruned,
+            vec![
+                notes_dir.join("B.md"),
+                notes_dir
+                    .join("attachments")
+                    .join(format!("attachment-{}-001.png", short_id("id-B"))),
+            ]
+        );
+        assert!(outside.path().join("Notes").join("Elsewhere.md").exists());
+
+        Ok(())
+    }
+}